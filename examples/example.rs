@@ -1,75 +1,58 @@
-use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
+use wgpu_glyph::Text;
+
 use kakapo::app;
-use kakapo::view::{UserData, UserDataMut, View, ViewRefs, WidgetCache, WidgetTree};
-use kakapo::view_model::ViewModel;
+use kakapo::view;
+use kakapo::view::{View, ViewRefs, WidgetCache, WidgetTree};
+use kakapo::view_model::{Observable, ViewModel};
 use kakapo::widgets;
 use kakapo::widgets::ButtonDelegate;
 
 struct SharedState {
-    view_refs: ViewRefs,
-    first: AtomicBool,
-    second: AtomicBool,
+    first: Observable<bool>,
+    second: Observable<bool>,
 }
 
+#[derive(ViewModel)]
 struct AppData {
-    two_buttons: bool,
+    view_refs: ViewRefs,
+    two_buttons: Observable<bool>,
     shared_state: Arc<SharedState>,
 }
 
 impl AppData {
     fn new() -> AppData {
         AppData {
-            two_buttons: false,
+            view_refs: ViewRefs::new(),
+            two_buttons: Observable::new(false),
             shared_state: Arc::new(SharedState {
-                view_refs: ViewRefs::new(),
-                first: AtomicBool::new(false),
-                second: AtomicBool::new(false),
+                first: Observable::new(false),
+                second: Observable::new(false),
             }),
         }
     }
 }
 
-impl ViewModel for AppData {
-    fn view_refs(&self) -> &ViewRefs {
-        &self.shared_state.view_refs
-    }
-}
-
-#[derive(Copy, Clone)]
-struct PrimaryButtonDelegate;
-
-impl ButtonDelegate for PrimaryButtonDelegate {
-    fn pressed(&mut self, parent: UserDataMut<'_>) {
-        let app_data = parent.unwrap().downcast_mut::<AppData>().unwrap();
-        app_data.two_buttons = !app_data.two_buttons;
-        app_data.shared_state.view_refs.update();
-        println!("First");
-    }
-}
-
 #[derive(Copy, Clone)]
 struct SecondaryButtonDelegate(bool);
 
-impl ButtonDelegate for SecondaryButtonDelegate {
-    fn pressed(&mut self, parent: UserDataMut<'_>) {
+impl ButtonDelegate<AppData> for SecondaryButtonDelegate {
+    fn pressed(&mut self, app_data: &mut AppData) {
         println!("Second");
-        let app_data = parent.unwrap().downcast_mut::<AppData>().unwrap();
         let first = self.0;
         let state = Arc::clone(&app_data.shared_state);
         std::thread::spawn(move || {
             std::thread::sleep(Duration::from_secs(2));
             let field = if first { &state.first } else { &state.second };
-            field.fetch_xor(true, Ordering::SeqCst);
-            state.view_refs.update();
+            field.modify(|value| *value = !*value);
         });
     }
 }
 
-fn secondary_button_colour(data: &AtomicBool) -> [f32; 4] {
-    if data.load(Ordering::SeqCst) {
+fn secondary_button_colour(data: &Observable<bool>) -> [f32; 4] {
+    if data.get() {
         [0.0, 1.0, 1.0, 1.0]
     } else {
         [0.0, 0.0, 1.0, 1.0]
@@ -79,38 +62,39 @@ fn secondary_button_colour(data: &AtomicBool) -> [f32; 4] {
 struct AppView {}
 
 impl View for AppView {
-    fn view(&mut self, cache: &mut WidgetCache, user_data: UserData<'_>) -> WidgetTree {
+    type Model = AppData;
+
+    fn view(&mut self, cache: &mut WidgetCache<AppData>, data: &AppData) -> WidgetTree {
         println!("View!");
-        let data = user_data.unwrap().downcast_ref::<AppData>().unwrap();
-        let mut b = widgets::Box::new()
-            .append(
-                widgets::Button::new(
-                    if data.two_buttons {
-                        [0.0, 1.0, 0.0, 1.0]
-                    } else {
-                        [1.0, 0.0, 0.0, 1.0]
+        let primary_colour = if data.two_buttons.get() {
+            [0.0, 1.0, 0.0, 1.0]
+        } else {
+            [1.0, 0.0, 0.0, 1.0]
+        };
+        cache.build(view! {
+            widgets::Box {
+                widgets::Button(
+                    primary_colour,
+                    |data: &mut AppData| {
+                        data.two_buttons.modify(|two_buttons| *two_buttons = !*two_buttons);
+                        println!("First");
                     },
-                    PrimaryButtonDelegate,
+                    add_text = &Text::new("Primary"),
                 )
-                .add_text(&wgpu_glyph::Text::new("Primary")),
-            )
-            .append(
-                widgets::Button::new(
+                widgets::Button(
                     secondary_button_colour(&data.shared_state.first),
                     SecondaryButtonDelegate(true),
+                    add_text = &Text::new("A"),
                 )
-                .add_text(&wgpu_glyph::Text::new("A")),
-            );
-        if data.two_buttons {
-            b = b.append(
-                widgets::Button::new(
-                    secondary_button_colour(&data.shared_state.second),
-                    SecondaryButtonDelegate(false),
-                )
-                .add_text(&wgpu_glyph::Text::new("B")),
-            );
-        }
-        cache.build(b)
+                if data.two_buttons.get() {
+                    widgets::Button(
+                        secondary_button_colour(&data.shared_state.second),
+                        SecondaryButtonDelegate(false),
+                        add_text = &Text::new("B"),
+                    )
+                }
+            }
+        })
     }
 }
 
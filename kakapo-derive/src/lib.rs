@@ -0,0 +1,411 @@
+//! Macros for kakapo, use them through the re-exports in the kakapo crate.
+
+extern crate proc_macro;
+
+mod view;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::punctuated::Punctuated;
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Error, Field, Fields, Ident, Lit,
+    Meta, NestedMeta, Token, Type, WherePredicate,
+};
+
+/// Implements `ViewModel` for a struct with a `ViewRefs` field, or a field marked `#[view_refs]`.
+#[proc_macro_derive(ViewModel, attributes(view_refs))]
+pub fn derive_view_model(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    view_model(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+/// Implements `Description` for a struct holding the properties of a widget. The widget is given
+/// by `#[description(widget = "Type")]`, and is created from the properties with its `From` impl.
+/// When the description is applied to an existing widget each property is copied into the
+/// widget's field of the same name, except for the key and fields marked `#[description(skip)]`.
+///
+/// The key is the field of type `WidgetKey`, or the field marked `#[key]`. The description is for
+/// views of any model, unless the model is given by `#[description(model = "Type")]`. The type
+/// parameter for any model can be named with `#[description(model_param = "M")]`, so that the
+/// widget type and extra bounds, e.g. `#[description(bound = "D: MyDelegate<M>")]`, can use it.
+#[proc_macro_derive(Description, attributes(description, key))]
+pub fn derive_description(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    description(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+/// Declares a tree of descriptions, giving the root description to build with `WidgetCache::build`.
+///
+/// ```ignore
+/// cache.build(view! {
+///     widgets::Box {
+///         widgets::Button(colour, |data: &mut AppData| data.count += 1, add_text = &Text::new("Add"))
+///         if data.show_items {
+///             for item in &data.items {
+///                 widgets::Button(item.colour, ItemDelegate(item.id), key = item.id)
+///             }
+///         } else {
+///             { self.placeholder() }
+///         }
+///     }
+/// })
+/// ```
+///
+/// An element `Path(args, name = value) { children }` is created by `Path::new(args)`, then each
+/// attribute calls the builder method `name(value)`, except `key = value` which calls `with_key`.
+/// The children, which can be elements, `if` and `for` blocks, or `{ expr }` giving any
+/// description, are added with `append`. Elements within loops need keys to keep their widgets.
+#[proc_macro]
+pub fn view(input: TokenStream) -> TokenStream {
+    let element = parse_macro_input!(input as view::Element);
+    view::expand(&element).into()
+}
+
+fn view_model(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = named_fields(&input)?;
+    let view_refs = find_field(fields, "view_refs", "ViewRefs").ok_or_else(|| {
+        Error::new_spanned(
+            &input.ident,
+            "ViewModel needs a ViewRefs field, or a field marked #[view_refs]",
+        )
+    })?;
+    let view_refs = &view_refs.ident;
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::kakapo::view_model::ViewModel for #ident #ty_generics #where_clause {
+            fn view_refs(&self) -> &::kakapo::view::ViewRefs {
+                &self.#view_refs
+            }
+        }
+    })
+}
+
+fn description(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = named_fields(&input)?;
+    let key = find_field(fields.clone(), "key", "WidgetKey").ok_or_else(|| {
+        Error::new_spanned(
+            &input.ident,
+            "Description needs a WidgetKey field, or a field marked #[key]",
+        )
+    })?;
+    let key = key.ident.as_ref().unwrap();
+
+    let mut widget = None;
+    let mut model = None;
+    let mut model_param = None;
+    let mut bounds = Vec::new();
+    for (name, value) in description_args(&input.attrs)? {
+        let value = match value {
+            Some(Lit::Str(value)) => value,
+            _ => return Err(Error::new_spanned(name, "expected a string")),
+        };
+        if name.is_ident("widget") {
+            widget = Some(value.parse::<Type>()?);
+        } else if name.is_ident("model") {
+            model = Some(value.parse::<Type>()?);
+        } else if name.is_ident("model_param") {
+            model_param = Some(value.parse::<Ident>()?);
+        } else if name.is_ident("bound") {
+            let parser = Punctuated::<WherePredicate, Token![,]>::parse_terminated;
+            bounds.extend(value.parse_with(parser)?);
+        } else {
+            return Err(Error::new_spanned(name, "unknown description argument"));
+        }
+    }
+    let widget = widget.ok_or_else(|| {
+        Error::new_spanned(
+            &input.ident,
+            "Description needs the widget type, e.g. #[description(widget = \"MyWidget\")]",
+        )
+    })?;
+
+    let mut copied = Vec::new();
+    for field in fields {
+        let ident = field.ident.as_ref().unwrap();
+        let mut skip = ident == key;
+        for (name, value) in description_args(&field.attrs)? {
+            if name.is_ident("skip") && value.is_none() {
+                skip = true;
+            } else {
+                return Err(Error::new_spanned(name, "unknown description argument"));
+            }
+        }
+        if !skip {
+            copied.push(ident);
+        }
+    }
+
+    // Descriptions without a model are generic over the model
+    let mut generics = input.generics.clone();
+    let model = match model {
+        Some(model) => model,
+        None => {
+            let param = model_param.unwrap_or_else(|| parse_quote!(__KakapoModel));
+            generics.params.push(parse_quote!(#param));
+            parse_quote!(#param)
+        }
+    };
+    generics.make_where_clause().predicates.extend(bounds);
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+    let ident = &input.ident;
+    Ok(quote! {
+        impl #impl_generics ::kakapo::Description<#model> for #ident #ty_generics #where_clause {
+            fn key(&self) -> ::std::option::Option<::kakapo::view::WidgetKey> {
+                ::std::option::Option::Some(::std::clone::Clone::clone(&self.#key))
+            }
+
+            fn apply(
+                self,
+                obj: &mut dyn ::std::any::Any,
+            ) -> ::std::result::Result<(), Self> {
+                match obj.downcast_mut::<#widget>() {
+                    ::std::option::Option::Some(widget) => {
+                        #(widget.#copied = self.#copied;)*
+                        ::std::result::Result::Ok(())
+                    }
+                    ::std::option::Option::None => ::std::result::Result::Err(self),
+                }
+            }
+
+            fn create(
+                self,
+                cache: &mut ::kakapo::view::WidgetCache<#model>,
+            ) -> ::kakapo::view::WidgetTree {
+                let key = ::std::clone::Clone::clone(&self.#key);
+                let widget = <#widget as ::std::convert::From<Self>>::from(self);
+                cache.factory().new_widget(key, widget)
+            }
+        }
+    })
+}
+
+fn named_fields(input: &DeriveInput) -> syn::Result<impl Iterator<Item = &Field> + Clone> {
+    match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => Ok(fields.named.iter()),
+            _ => Err(Error::new_spanned(
+                &input.ident,
+                "only structs with named fields are supported",
+            )),
+        },
+        _ => Err(Error::new_spanned(
+            &input.ident,
+            "only structs with named fields are supported",
+        )),
+    }
+}
+
+/// Finds the field marked with the attribute, otherwise the field whose type is named `ty`.
+fn find_field<'a>(
+    fields: impl Iterator<Item = &'a Field> + Clone,
+    attribute: &str,
+    ty: &str,
+) -> Option<&'a Field> {
+    fields
+        .clone()
+        .find(|field| field.attrs.iter().any(|attr| attr.path.is_ident(attribute)))
+        .or_else(|| {
+            fields.clone().find(|field| match &field.ty {
+                Type::Path(path) => {
+                    matches!(path.path.segments.last(), Some(segment) if segment.ident == ty)
+                }
+                _ => false,
+            })
+        })
+}
+
+/// The arguments of `#[description(...)]` attributes, either `name = "value"` or just `name`.
+fn description_args(attrs: &[Attribute]) -> syn::Result<Vec<(syn::Path, Option<Lit>)>> {
+    let mut args = Vec::new();
+    for attr in attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("description"))
+    {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => return Err(Error::new_spanned(meta, "expected #[description(...)]")),
+        };
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::NameValue(name_value)) => {
+                    args.push((name_value.path, Some(name_value.lit)))
+                }
+                NestedMeta::Meta(Meta::Path(path)) => args.push((path, None)),
+                nested => return Err(Error::new_spanned(nested, "unexpected argument")),
+            }
+        }
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(input: DeriveInput) -> String {
+        description(input).unwrap().to_string()
+    }
+
+    fn error(input: DeriveInput) -> String {
+        match description(input) {
+            Ok(output) => panic!("expected an error, got {}", output),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn view_model_finds_view_refs() {
+        let by_type = view_model(parse_quote! {
+            struct AppData {
+                count: Observable<u64>,
+                refs: ViewRefs,
+            }
+        })
+        .unwrap()
+        .to_string();
+        assert!(by_type.contains(&quote!(&self.refs).to_string()));
+
+        let marked = view_model(parse_quote! {
+            struct AppData {
+                #[view_refs]
+                refs: kakapo::view::ViewRefs,
+            }
+        })
+        .unwrap()
+        .to_string();
+        assert!(marked.contains(&quote!(&self.refs).to_string()));
+
+        let missing = view_model(parse_quote! {
+            struct AppData {
+                count: Observable<u64>,
+            }
+        });
+        assert!(missing
+            .unwrap_err()
+            .to_string()
+            .starts_with("ViewModel needs a ViewRefs field"));
+    }
+
+    #[test]
+    fn copies_fields_except_key_and_skipped() {
+        let output = expand(parse_quote! {
+            #[description(widget = "LabelWidget")]
+            struct Label {
+                text: String,
+                #[description(skip)]
+                font: Font,
+                key: WidgetKey,
+            }
+        });
+        assert!(output.contains(&quote!(widget.text = self.text;).to_string()));
+        assert!(!output.contains(&quote!(widget.font).to_string()));
+        assert!(!output.contains(&quote!(widget.key).to_string()));
+        assert!(output.contains(&quote!(obj.downcast_mut::<LabelWidget>()).to_string()));
+        assert!(output.contains(&quote!(self.key).to_string()));
+    }
+
+    #[test]
+    fn key_attribute_overrides_key_type() {
+        let output = expand(parse_quote! {
+            #[description(widget = "LabelWidget")]
+            struct Label {
+                fallback: WidgetKey,
+                #[key]
+                id: Key,
+            }
+        });
+        assert!(output.contains(&quote!(widget.fallback = self.fallback;).to_string()));
+        assert!(!output.contains(&quote!(widget.id).to_string()));
+        assert!(output.contains(&quote!(::std::clone::Clone::clone(&self.id)).to_string()));
+    }
+
+    #[test]
+    fn model_is_generic_unless_given() {
+        let any_model = expand(parse_quote! {
+            #[description(widget = "LabelWidget<M>", model_param = "M", bound = "M: Clone")]
+            struct Label<T> {
+                text: T,
+                key: WidgetKey,
+            }
+        });
+        assert!(any_model.contains(
+            &quote!(impl<T, M> ::kakapo::Description<M> for Label<T> where M: Clone).to_string()
+        ));
+
+        let model = expand(parse_quote! {
+            #[description(widget = "LabelWidget", model = "AppData")]
+            struct Label {
+                key: WidgetKey,
+            }
+        });
+        assert!(model.contains(&quote!(impl ::kakapo::Description<AppData> for Label).to_string()));
+        assert!(
+            model.contains(&quote!(cache: &mut ::kakapo::view::WidgetCache<AppData>).to_string())
+        );
+    }
+
+    #[test]
+    fn argument_errors() {
+        let missing_widget = error(parse_quote! {
+            struct Label {
+                key: WidgetKey,
+            }
+        });
+        assert!(missing_widget.starts_with("Description needs the widget type"));
+
+        let missing_key = error(parse_quote! {
+            #[description(widget = "LabelWidget")]
+            struct Label {
+                text: String,
+            }
+        });
+        assert!(missing_key.starts_with("Description needs a WidgetKey field"));
+
+        let not_a_string = error(parse_quote! {
+            #[description(widget = 1)]
+            struct Label {
+                key: WidgetKey,
+            }
+        });
+        assert_eq!(not_a_string, "expected a string");
+
+        let unknown = error(parse_quote! {
+            #[description(widget = "LabelWidget", colour = "red")]
+            struct Label {
+                key: WidgetKey,
+            }
+        });
+        assert_eq!(unknown, "unknown description argument");
+
+        let skip_with_value = error(parse_quote! {
+            #[description(widget = "LabelWidget")]
+            struct Label {
+                #[description(skip = "yes")]
+                text: String,
+                key: WidgetKey,
+            }
+        });
+        assert_eq!(skip_with_value, "unknown description argument");
+
+        let not_a_list = error(parse_quote! {
+            #[description = "LabelWidget"]
+            struct Label {
+                key: WidgetKey,
+            }
+        });
+        assert_eq!(not_a_list, "expected #[description(...)]");
+
+        let tuple = error(parse_quote! {
+            #[description(widget = "LabelWidget")]
+            struct Label(WidgetKey);
+        });
+        assert_eq!(tuple, "only structs with named fields are supported");
+    }
+}
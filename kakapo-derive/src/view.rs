@@ -0,0 +1,300 @@
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{quote, quote_spanned};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{braced, parenthesized, Expr, Ident, Pat, Path, Token};
+
+/// A description, e.g. `widgets::Button(colour, delegate, key = id) { children }`.
+pub(crate) struct Element {
+    path: Path,
+    args: Vec<Expr>,
+    attrs: Vec<(Ident, Expr)>,
+    children: Vec<Node>,
+}
+
+enum Node {
+    Element(Element),
+    If {
+        cond: Expr,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    For {
+        pat: Pat,
+        expr: Expr,
+        body: Vec<Node>,
+    },
+    /// Any expression giving a description, e.g. `{ self.sidebar(data) }`.
+    Expr(Expr),
+}
+
+enum Arg {
+    Positional(Expr),
+    Attr(Ident, Expr),
+}
+
+impl Parse for Element {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let path = input.parse()?;
+        let mut args = Vec::new();
+        let mut attrs = Vec::new();
+        if input.peek(syn::token::Paren) {
+            let content;
+            parenthesized!(content in input);
+            for arg in Punctuated::<Arg, Token![,]>::parse_terminated(&content)? {
+                match arg {
+                    Arg::Positional(expr) => args.push(expr),
+                    Arg::Attr(name, value) => attrs.push((name, value)),
+                }
+            }
+        }
+        let children = if input.peek(syn::token::Brace) {
+            parse_nodes(input)?
+        } else {
+            Vec::new()
+        };
+        Ok(Element {
+            path,
+            args,
+            attrs,
+            children,
+        })
+    }
+}
+
+impl Parse for Arg {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(Ident) && input.peek2(Token![=]) && !input.peek2(Token![==]) {
+            let name = input.parse()?;
+            input.parse::<Token![=]>()?;
+            Ok(Arg::Attr(name, input.parse()?))
+        } else {
+            Ok(Arg::Positional(input.parse()?))
+        }
+    }
+}
+
+impl Parse for Node {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(Token![if]) {
+            input.parse::<Token![if]>()?;
+            let cond = Expr::parse_without_eager_brace(input)?;
+            let then = parse_nodes(input)?;
+            let otherwise = if input.peek(Token![else]) {
+                input.parse::<Token![else]>()?;
+                if input.peek(Token![if]) {
+                    vec![input.parse()?]
+                } else {
+                    parse_nodes(input)?
+                }
+            } else {
+                Vec::new()
+            };
+            Ok(Node::If {
+                cond,
+                then,
+                otherwise,
+            })
+        } else if input.peek(Token![for]) {
+            input.parse::<Token![for]>()?;
+            let pat = input.parse()?;
+            input.parse::<Token![in]>()?;
+            let expr = Expr::parse_without_eager_brace(input)?;
+            let body = parse_nodes(input)?;
+            Ok(Node::For { pat, expr, body })
+        } else if input.peek(syn::token::Brace) {
+            let content;
+            braced!(content in input);
+            Ok(Node::Expr(content.parse()?))
+        } else {
+            Ok(Node::Element(input.parse()?))
+        }
+    }
+}
+
+/// Parses `{ node node ... }`, the nodes can optionally be separated by commas.
+fn parse_nodes(input: ParseStream) -> syn::Result<Vec<Node>> {
+    let content;
+    braced!(content in input);
+    let mut nodes = Vec::new();
+    while !content.is_empty() {
+        nodes.push(content.parse()?);
+        if content.peek(Token![,]) {
+            content.parse::<Token![,]>()?;
+        }
+    }
+    Ok(nodes)
+}
+
+/// Expands to an expression giving the element's description. The element is created with
+/// `Path::new(args)`, each attribute `name = value` calls the builder method `name(value)`, except
+/// for `key = value` which calls `with_key(value)`, and each child is added with `append(child)`.
+pub(crate) fn expand(element: &Element) -> TokenStream2 {
+    let path = &element.path;
+    let args = &element.args;
+    // Spanned to the element so that the widget key's call-site is in the user's view
+    let mut expr = quote_spanned!(path.span()=> #path::new(#(#args),*));
+    for (name, value) in &element.attrs {
+        expr = if name == "key" {
+            quote_spanned!(name.span()=> #expr.with_key(#value))
+        } else {
+            quote_spanned!(name.span()=> #expr.#name(#value))
+        };
+    }
+    if element.children.is_empty() {
+        return expr;
+    }
+    let children = expand_nodes(&element.children);
+    // `__element` is resolved at the macro's definition, so that it can't hide the user's names
+    quote_spanned! {Span::mixed_site()=>
+        {
+            let mut __element = #expr;
+            #children
+            __element
+        }
+    }
+}
+
+fn expand_nodes(nodes: &[Node]) -> TokenStream2 {
+    nodes
+        .iter()
+        .map(|node| match node {
+            Node::Element(element) => {
+                let child = expand(element);
+                quote_spanned!(Span::mixed_site()=> __element = __element.append(#child);)
+            }
+            Node::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                let then = expand_nodes(then);
+                let otherwise = expand_nodes(otherwise);
+                quote!(if #cond { #then } else { #otherwise })
+            }
+            Node::For { pat, expr, body } => {
+                let body = expand_nodes(body);
+                quote!(for #pat in #expr { #body })
+            }
+            Node::Expr(expr) => {
+                quote_spanned!(Span::mixed_site()=> __element = __element.append(#expr);)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_str(input: &str) -> String {
+        expand(&syn::parse_str(input).unwrap()).to_string()
+    }
+
+    #[test]
+    fn element_args_and_attributes() {
+        assert_eq!(
+            expand_str("widgets::Button(colour, delegate, key = item.id, add_text = &text)"),
+            quote!(widgets::Button::new(colour, delegate)
+                .with_key(item.id)
+                .add_text(&text))
+            .to_string()
+        );
+        // Comparisons are arguments rather than attributes
+        assert_eq!(
+            expand_str("Label(shown == true)"),
+            quote!(Label::new(shown == true)).to_string()
+        );
+        assert_eq!(expand_str("Label"), quote!(Label::new()).to_string());
+    }
+
+    #[test]
+    fn children_are_appended() {
+        assert_eq!(
+            expand_str("widgets::Box(key = 1) { Label(a), { self.sidebar() }, Label(b) }"),
+            quote!({
+                let mut __element = widgets::Box::new().with_key(1);
+                __element = __element.append(Label::new(a));
+                __element = __element.append(self.sidebar());
+                __element = __element.append(Label::new(b));
+                __element
+            })
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn if_and_else_if() {
+        assert_eq!(
+            expand_str("Box { if a { Label(1) } else if b { Label(2) } else { Label(3) } }"),
+            quote!({
+                let mut __element = Box::new();
+                if a {
+                    __element = __element.append(Label::new(1));
+                } else {
+                    if b {
+                        __element = __element.append(Label::new(2));
+                    } else {
+                        __element = __element.append(Label::new(3));
+                    }
+                }
+                __element
+            })
+            .to_string()
+        );
+        assert_eq!(
+            expand_str("Box { if a { Label(1) } }"),
+            quote!({
+                let mut __element = Box::new();
+                if a {
+                    __element = __element.append(Label::new(1));
+                } else {
+                }
+                __element
+            })
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn for_loops() {
+        assert_eq!(
+            expand_str(
+                "Box { for (i, item) in items.iter().enumerate() { Label(item, key = i) } }"
+            ),
+            quote!({
+                let mut __element = Box::new();
+                for (i, item) in items.iter().enumerate() {
+                    __element = __element.append(Label::new(item).with_key(i));
+                }
+                __element
+            })
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn nested_elements() {
+        assert_eq!(
+            expand_str("Outer { Inner { Label } }"),
+            quote!({
+                let mut __element = Outer::new();
+                __element = __element.append({
+                    let mut __element = Inner::new();
+                    __element = __element.append(Label::new());
+                    __element
+                });
+                __element
+            })
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(syn::parse_str::<Element>("Box { for item { Label } }").is_err());
+        assert!(syn::parse_str::<Element>("Box { if a Label }").is_err());
+        assert!(syn::parse_str::<Element>("Box(key =)").is_err());
+    }
+}
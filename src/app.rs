@@ -6,10 +6,10 @@ use std::sync::{Arc, Mutex};
 use winit::platform::unix::EventLoopExtUnix;
 
 use crate::events::EventState;
-use crate::geom::{Position, Rect};
-use crate::renderer::Renderer;
-use crate::view::{View, ViewId, WidgetTree, WidgetTreeFactory};
-use crate::view_model::ViewModel;
+use crate::geom::{Constraints, Position, Size};
+use crate::renderer::display_list::DisplayList;
+use crate::renderer::WindowRenderer;
+use crate::view::{LayoutContext, View, ViewId, WidgetId, WidgetTree, WidgetTreeFactory};
 
 pub struct AppBuilder {
     windows: HashMap<winit::window::WindowId, Window>,
@@ -19,19 +19,18 @@ pub struct AppBuilder {
 
 impl AppBuilder {
     pub fn new() -> AppBuilder {
-        let app_inner = Arc::new(AppInner::new());
+        let event_loop = winit::event_loop::EventLoop::<()>::new_x11_any_thread().unwrap();
+        let app_inner = Arc::new(AppInner::new(Some(event_loop.create_proxy())));
         AppBuilder {
             windows: HashMap::new(),
-            event_loop: winit::event_loop::EventLoop::<()>::new_x11_any_thread().unwrap(),
+            event_loop,
             app_inner,
         }
     }
 
-    pub fn add_window<V: View + 'static, D: ViewModel + 'static>(&mut self, root: V, user_data: D) {
-        let factory = WidgetTreeFactory {
-            app: Arc::clone(&self.app_inner),
-        };
-        let window = Window::create(factory.new_view(root, user_data), &self.event_loop);
+    pub fn add_window<V: View + 'static>(&mut self, root: V, model: V::Model) {
+        let factory = WidgetTreeFactory::<()>::new(Arc::clone(&self.app_inner));
+        let window = Window::create(factory.new_view(root, model), &self.event_loop);
         self.windows.insert(window.window_id(), window);
     }
 
@@ -61,14 +60,22 @@ impl AppBuilder {
 
 pub(crate) struct AppInner {
     view_id_counter: AtomicU64,
+    widget_id_counter: AtomicU64,
     views_to_update: Mutex<HashSet<ViewId>>,
+    widgets_to_update: Mutex<HashSet<WidgetId>>,
+    // Wakes the event loop when views are updated from other threads, the proxy isn't Sync. None
+    // for headless apps, which don't have an event loop.
+    event_loop_proxy: Option<Mutex<winit::event_loop::EventLoopProxy<()>>>,
 }
 
 impl AppInner {
-    fn new() -> AppInner {
+    pub(crate) fn new(event_loop_proxy: Option<winit::event_loop::EventLoopProxy<()>>) -> AppInner {
         AppInner {
             view_id_counter: AtomicU64::new(0),
+            widget_id_counter: AtomicU64::new(0),
             views_to_update: Mutex::new(HashSet::new()),
+            widgets_to_update: Mutex::new(HashSet::new()),
+            event_loop_proxy: event_loop_proxy.map(Mutex::new),
         }
     }
 
@@ -76,8 +83,46 @@ impl AppInner {
         ViewId(self.view_id_counter.fetch_add(1, Ordering::Relaxed))
     }
 
+    pub(crate) fn new_widget_id(&self) -> WidgetId {
+        WidgetId(self.widget_id_counter.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Marks the view to be rebuilt, waking the event loop so that the view is rebuilt even when
+    /// no other events arrive.
     pub(crate) fn update_view(&self, view_id: ViewId) {
-        self.views_to_update.lock().unwrap().insert(view_id);
+        let mut views_to_update = self.views_to_update.lock().unwrap();
+        let wake = views_to_update.is_empty();
+        views_to_update.insert(view_id);
+        drop(views_to_update);
+        if wake {
+            self.wake();
+        }
+    }
+
+    /// Marks the widget to be updated, see `Widget::update`.
+    pub(crate) fn update_widget(&self, widget_id: WidgetId) {
+        let mut widgets_to_update = self.widgets_to_update.lock().unwrap();
+        let wake = widgets_to_update.is_empty();
+        widgets_to_update.insert(widget_id);
+        drop(widgets_to_update);
+        if wake {
+            self.wake();
+        }
+    }
+
+    fn wake(&self) {
+        if let Some(event_loop_proxy) = &self.event_loop_proxy {
+            // Fails once the event loop has exited, in which case there's nothing to update
+            let _ = event_loop_proxy.lock().unwrap().send_event(());
+        }
+    }
+
+    /// Takes the views and widgets which need updating.
+    pub(crate) fn take_updates(&self) -> (HashSet<ViewId>, HashSet<WidgetId>) {
+        (
+            std::mem::take(self.views_to_update.lock().unwrap().deref_mut()),
+            std::mem::take(self.widgets_to_update.lock().unwrap().deref_mut()),
+        )
     }
 }
 
@@ -102,22 +147,16 @@ impl App {
 
                 let window = self.windows.get_mut(&window_id).expect("Got window");
                 window.handle_event(window_event);
+                window.redraw_if_damaged();
             }
             winit::event::Event::MainEventsCleared => {
-                let views_to_update =
-                    std::mem::take(self.inner.views_to_update.lock().unwrap().deref_mut());
+                let (views_to_update, widgets_to_update) = self.inner.take_updates();
+                if views_to_update.is_empty() && widgets_to_update.is_empty() {
+                    return;
+                }
                 for window in self.windows.values_mut() {
-                    if window.root.update(&views_to_update, None) {
-                        let size = window
-                            .window
-                            .inner_size()
-                            .to_logical::<f32>(window.window.scale_factor());
-                        window
-                            .root
-                            .set_rect(Rect::new(Position::zero(), size.into()));
-                    }
-                    // TODO don't unconditionally redraw
-                    window.window.request_redraw();
+                    window.update(&views_to_update, &widgets_to_update);
+                    window.redraw_if_damaged();
                 }
             }
             winit::event::Event::RedrawRequested(window_id) => {
@@ -133,7 +172,7 @@ struct Window {
     root: WidgetTree,
     window: winit::window::Window,
     events: EventState,
-    renderer: Renderer,
+    renderer: WindowRenderer,
 }
 
 impl Window {
@@ -141,20 +180,26 @@ impl Window {
         mut root: WidgetTree,
         window_target: &winit::event_loop::EventLoopWindowTarget<()>,
     ) -> Window {
-        root.materialise_views(None);
-        let size = root.size_hint();
-        root.set_rect(Rect::new(Position::zero(), size));
+        root.materialise_views();
+        let size_hint = root.size_hint();
+        let size = size_hint.preferred;
+        root.layout(LayoutContext::new(
+            Position::zero(),
+            Constraints::tight(size),
+        ));
         let logical_size = winit::dpi::LogicalSize::new(size.width, size.height);
+        let min_size = winit::dpi::LogicalSize::new(size_hint.min.width, size_hint.min.height);
         let winit_window = winit::window::WindowBuilder::new()
             .with_title("Kakapo")
             .with_inner_size(logical_size)
+            .with_min_inner_size(min_size)
             .build(window_target)
             .expect("Failed to create window");
 
         use futures::executor::block_on;
 
-        let renderer = block_on(Renderer::new(&winit_window));
-        let events = EventState::new(&winit_window);
+        let renderer = block_on(WindowRenderer::new(&winit_window));
+        let events = EventState::new(winit_window.scale_factor());
 
         Window {
             root,
@@ -172,9 +217,15 @@ impl Window {
         match window_event {
             winit::event::WindowEvent::Resized(physical_size) => {
                 self.renderer.resize(physical_size);
+                self.layout();
             }
-            winit::event::WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+            winit::event::WindowEvent::ScaleFactorChanged {
+                scale_factor,
+                new_inner_size,
+            } => {
+                self.events.set_scale_factor(scale_factor);
                 self.renderer.resize(*new_inner_size);
+                self.layout();
             }
             event => {
                 self.events.process_event(event, &mut self.root);
@@ -182,11 +233,46 @@ impl Window {
         }
     }
 
+    /// Rebuilds the views and widgets which need updating and lays out what changed.
+    fn update(&mut self, views: &HashSet<ViewId>, widgets: &HashSet<WidgetId>) {
+        if self.root.update(views, widgets, self.events.damage()) {
+            self.layout();
+        }
+    }
+
+    /// Lays out the root to fill the window, which is then painted again.
+    fn layout(&mut self) {
+        let size: Size = self
+            .window
+            .inner_size()
+            .to_logical::<f32>(self.window.scale_factor())
+            .into();
+        let rect = self.root.layout(LayoutContext::new(
+            Position::zero(),
+            Constraints::tight(size),
+        ));
+        self.events.damage().add(rect);
+    }
+
+    /// Asks for the window to be painted if anything in it changed. The swap chain's frames don't
+    /// keep their contents, so the whole window is painted rather than just the damaged area.
+    fn redraw_if_damaged(&mut self) {
+        if !self.events.damage().is_empty() {
+            self.window.request_redraw();
+        }
+    }
+
     pub(crate) fn paint(&mut self) {
-        let root = &mut self.root;
-        match self.renderer.render(self.window.scale_factor(), |painter| {
-            root.paint(None, painter)
-        }) {
+        self.events.damage().take();
+        let scale_factor = self.window.scale_factor();
+        let size: Size = self
+            .window
+            .inner_size()
+            .to_logical::<f32>(scale_factor)
+            .into();
+        let root = &self.root;
+        let display_list = DisplayList::record(size, |painter| root.paint(None, painter));
+        match self.renderer.render(scale_factor, &display_list) {
             Ok(_) => {}
             Err(wgpu::SwapChainError::Lost) => self.renderer.recreate(),
             Err(wgpu::SwapChainError::OutOfMemory) => panic!("swapchain: out of memory"),
@@ -1,37 +1,56 @@
 use std::any::Any;
+use std::marker::PhantomData;
 
 use crate::view::{WidgetCache, WidgetKey, WidgetTree};
 
-pub trait Description {
+pub use kakapo_derive::Description;
+
+/// Describes a widget within a view whose model is `M`, so that widgets which use the model can
+/// only be described in views of that model.
+pub trait Description<M> {
     fn key(&self) -> Option<WidgetKey>;
 
+    /// Takes the descriptions of the widget's children. These are built by the cache, which
+    /// then gives them to the widget after `apply` or `create`, so that they are reconciled
+    /// independently of their parent.
+    fn children(&mut self) -> Vec<BoxedDescription<M>> {
+        Vec::new()
+    }
+
     fn apply(self, obj: &mut dyn Any) -> Result<(), Self>
     where
         Self: std::marker::Sized;
 
-    fn create(self, cache: &mut WidgetCache) -> WidgetTree
+    fn create(self, cache: &mut WidgetCache<M>) -> WidgetTree
     where
         Self: std::marker::Sized;
 }
 
-trait DynDescription {
+trait DynDescription<M> {
     fn key(&self) -> Option<WidgetKey>;
 
+    fn children(&mut self) -> Vec<BoxedDescription<M>>;
+
     fn apply(&mut self, obj: &mut dyn Any) -> bool;
 
-    fn create(self: Box<Self>, cache: &mut WidgetCache) -> WidgetTree;
+    fn create(self: Box<Self>, cache: &mut WidgetCache<M>) -> WidgetTree;
 }
 
-struct DynDescriptionImpl<D: Description> {
+struct DynDescriptionImpl<D: Description<M>, M> {
     // will only be None when calling apply on it's inner value
     desc: Option<D>,
+    model: PhantomData<fn(&mut M)>,
 }
 
-impl<D: Description> DynDescription for DynDescriptionImpl<D> {
+impl<D: Description<M>, M> DynDescription<M> for DynDescriptionImpl<D, M> {
     fn key(&self) -> Option<WidgetKey> {
         self.desc.as_ref().unwrap().key()
     }
 
+    fn children(&mut self) -> Vec<BoxedDescription<M>> {
+        self.desc.as_mut().unwrap().children()
+    }
+
     fn apply(&mut self, obj: &mut dyn Any) -> bool {
         match self.desc.take().unwrap().apply(obj) {
             Ok(()) => true,
@@ -42,28 +61,35 @@ impl<D: Description> DynDescription for DynDescriptionImpl<D> {
         }
     }
 
-    fn create(self: Box<Self>, cache: &mut WidgetCache) -> WidgetTree {
+    fn create(self: Box<Self>, cache: &mut WidgetCache<M>) -> WidgetTree {
         self.desc.unwrap().create(cache)
     }
 }
 
-pub struct BoxedDescription {
-    inner: Box<dyn DynDescription>,
+pub struct BoxedDescription<M> {
+    inner: Box<dyn DynDescription<M>>,
 }
 
-impl BoxedDescription {
-    pub fn new<D: Description + 'static>(desc: D) -> BoxedDescription {
+impl<M: 'static> BoxedDescription<M> {
+    pub fn new<D: Description<M> + 'static>(desc: D) -> BoxedDescription<M> {
         BoxedDescription {
-            inner: Box::new(DynDescriptionImpl { desc: Some(desc) }),
+            inner: Box::new(DynDescriptionImpl {
+                desc: Some(desc),
+                model: PhantomData,
+            }),
         }
     }
 }
 
-impl Description for BoxedDescription {
+impl<M> Description<M> for BoxedDescription<M> {
     fn key(&self) -> Option<WidgetKey> {
         self.inner.key()
     }
 
+    fn children(&mut self) -> Vec<BoxedDescription<M>> {
+        self.inner.children()
+    }
+
     fn apply(mut self, obj: &mut dyn Any) -> Result<(), Self> {
         if self.inner.apply(obj) {
             Ok(())
@@ -72,7 +98,7 @@ impl Description for BoxedDescription {
         }
     }
 
-    fn create(self, cache: &mut WidgetCache) -> WidgetTree {
+    fn create(self, cache: &mut WidgetCache<M>) -> WidgetTree {
         self.inner.create(cache)
     }
 }
@@ -1,29 +1,244 @@
+use std::time::{Duration, Instant};
+
 use winit::dpi::PhysicalPosition;
 
-use crate::geom::Position;
-use crate::view::WidgetTree;
+use crate::geom::{Position, Scalar};
+use crate::view::{Damage, WidgetId, WidgetTree, WindowState};
+
+pub use winit::event::{ModifiersState, MouseButton, VirtualKeyCode};
+
+/// Maximum time between presses for them to count as a multi-click.
+const MULTI_CLICK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Maximum distance, in logical pixels, the cursor can move between presses of a multi-click.
+const MULTI_CLICK_DISTANCE: f32 = 4.0;
 
 #[derive(Copy, Clone)]
 pub enum Event {
-    MousePress(Position),
-    MouseRelease(Position),
+    MousePress(MouseEvent),
+    MouseRelease(MouseEvent),
+    MouseMove(Position),
+    MouseEnter(Position),
+    MouseLeave,
+    Scroll(ScrollEvent),
+    KeyDown(KeyEvent),
+    KeyUp(KeyEvent),
+    Character(char),
+    FocusIn,
+    FocusOut,
+}
+
+impl Event {
+    /// The position of pointer events, relative to the widget receiving the event.
+    pub fn position(&self) -> Option<Position> {
+        match *self {
+            Event::MousePress(mouse_event) | Event::MouseRelease(mouse_event) => {
+                Some(mouse_event.position)
+            }
+            Event::MouseMove(pos) | Event::MouseEnter(pos) => Some(pos),
+            Event::Scroll(scroll_event) => Some(scroll_event.position),
+            _ => None,
+        }
+    }
+
+    /// Whether the event is passed on to the target's ancestors, events which notify a widget of a
+    /// change in its own state are only sent to that widget.
+    pub fn bubbles(&self) -> bool {
+        !matches!(
+            self,
+            Event::MouseEnter(_) | Event::MouseLeave | Event::FocusIn | Event::FocusOut
+        )
+    }
+
+    /// Makes the position of pointer events relative to the given origin.
+    pub(crate) fn relative_to(self, origin: Position) -> Event {
+        match self {
+            Event::MousePress(mouse_event) => Event::MousePress(mouse_event.relative_to(origin)),
+            Event::MouseRelease(mouse_event) => {
+                Event::MouseRelease(mouse_event.relative_to(origin))
+            }
+            Event::MouseMove(pos) => Event::MouseMove(pos - origin),
+            Event::MouseEnter(pos) => Event::MouseEnter(pos - origin),
+            Event::Scroll(scroll_event) => Event::Scroll(ScrollEvent {
+                position: scroll_event.position - origin,
+                ..scroll_event
+            }),
+            event => event,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct MouseEvent {
+    pub position: Position,
+    pub button: MouseButton,
+    pub modifiers: ModifiersState,
+    /// 1 for a single click, 2 for a double click and so on. A release has the same count as the
+    /// press before it.
+    pub click_count: u32,
+}
+
+impl MouseEvent {
+    fn relative_to(self, origin: Position) -> MouseEvent {
+        MouseEvent {
+            position: self.position - origin,
+            ..self
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ScrollEvent {
+    pub position: Position,
+    pub delta: ScrollDelta,
+    pub modifiers: ModifiersState,
+}
+
+/// How far to scroll, positive values move the content right and down, i.e. scroll towards the
+/// top left.
+#[derive(Copy, Clone, Debug)]
+pub enum ScrollDelta {
+    /// From scroll wheels, in lines.
+    Lines { x: Scalar, y: Scalar },
+    /// From touchpads, in logical pixels.
+    Pixels { x: Scalar, y: Scalar },
+}
+
+impl ScrollDelta {
+    pub fn to_pixels(self, line_height: Scalar) -> Position {
+        match self {
+            ScrollDelta::Lines { x, y } => Position::new(x * line_height, y * line_height),
+            ScrollDelta::Pixels { x, y } => Position::new(x, y),
+        }
+    }
+}
+
+/// Returned by widgets to say whether they used the event, unhandled events are passed on to the
+/// widget's parent.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum EventResult {
+    Handled,
+    Unhandled,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct KeyEvent {
+    pub scancode: u32,
+    pub key: Option<VirtualKeyCode>,
+    pub modifiers: ModifiersState,
+}
+
+/// Tracks which widget within a window receives keyboard input.
+#[derive(Default)]
+pub(crate) struct Focus {
+    owner: Option<WidgetId>,
+    // Only applied once the current event has been dispatched, so that the widgets losing and
+    // gaining focus can be notified.
+    requested: Option<Option<WidgetId>>,
+}
+
+impl Focus {
+    pub(crate) fn owner(&self) -> Option<WidgetId> {
+        self.owner
+    }
+
+    fn pending_owner(&self) -> Option<WidgetId> {
+        self.requested.unwrap_or(self.owner)
+    }
+
+    pub(crate) fn has_focus(&self, id: WidgetId) -> bool {
+        self.pending_owner() == Some(id)
+    }
+
+    pub(crate) fn request(&mut self, id: WidgetId) {
+        self.requested = Some(Some(id));
+    }
+
+    pub(crate) fn release(&mut self, id: WidgetId) {
+        if self.has_focus(id) {
+            self.requested = Some(None);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.owner = None;
+    }
+}
+
+/// Works out the click count of mouse presses, from how close in time and space they are to the
+/// previous press.
+struct ClickCounter {
+    last_press: Option<(Instant, Position, MouseButton)>,
+    count: u32,
+}
+
+impl ClickCounter {
+    fn new() -> ClickCounter {
+        ClickCounter {
+            last_press: None,
+            count: 0,
+        }
+    }
+
+    fn press(&mut self, pos: Position, button: MouseButton) -> u32 {
+        let now = Instant::now();
+        let is_multi_click = match self.last_press {
+            Some((time, last_pos, last_button)) => {
+                let delta = pos - last_pos;
+                last_button == button
+                    && now.duration_since(time) <= MULTI_CLICK_INTERVAL
+                    && delta.x.abs() <= MULTI_CLICK_DISTANCE
+                    && delta.y.abs() <= MULTI_CLICK_DISTANCE
+            }
+            None => false,
+        };
+        self.count = if is_multi_click { self.count + 1 } else { 1 };
+        self.last_press = Some((now, pos, button));
+        self.count
+    }
+
+    fn release(&self) -> u32 {
+        self.count.max(1)
+    }
 }
 
 pub(crate) struct EventState {
     cursor_position: winit::dpi::PhysicalPosition<f64>,
     current_modifiers: winit::event::ModifiersState,
     scale_factor: f64,
+    window: WindowState,
+    hovered: Option<WidgetId>,
+    // The widget that received the last mouse press, which then receives all pointer events until
+    // the mouse is released.
+    captured: Option<WidgetId>,
+    buttons_down: u32,
+    clicks: ClickCounter,
 }
 
 impl EventState {
-    pub(crate) fn new(window: &winit::window::Window) -> EventState {
+    pub(crate) fn new(scale_factor: f64) -> EventState {
         EventState {
             cursor_position: PhysicalPosition::new(0.0, 0.0),
             current_modifiers: winit::event::ModifiersState::default(),
-            scale_factor: window.scale_factor(),
+            scale_factor,
+            window: WindowState::default(),
+            hovered: None,
+            captured: None,
+            buttons_down: 0,
+            clicks: ClickCounter::new(),
         }
     }
 
+    /// Sets the scale factor which positions from the window are converted to logical pixels with.
+    pub(crate) fn set_scale_factor(&mut self, scale_factor: f64) {
+        self.scale_factor = scale_factor;
+    }
+
+    /// The area of the window which needs painting again.
+    pub(crate) fn damage(&mut self) -> &mut Damage {
+        &mut self.window.damage
+    }
+
     fn cursor_logical_position(&self) -> Position {
         let logical = self.cursor_position.to_logical(self.scale_factor);
         Position::new(logical.x, logical.y)
@@ -34,26 +249,257 @@ impl EventState {
         window_event: winit::event::WindowEvent<'_>,
         root: &mut WidgetTree,
     ) {
-        match window_event {
+        let event = match window_event {
             winit::event::WindowEvent::ModifiersChanged(state) => {
                 self.current_modifiers = state;
+                return;
             }
             winit::event::WindowEvent::CursorMoved { position, .. } => {
                 self.cursor_position = position;
+                Event::MouseMove(self.cursor_logical_position())
             }
+            winit::event::WindowEvent::CursorLeft { .. } => Event::MouseLeave,
             winit::event::WindowEvent::MouseInput {
                 state: winit::event::ElementState::Pressed,
+                button,
                 ..
             } => {
-                root.event(Event::MousePress(self.cursor_logical_position()));
+                let pos = self.cursor_logical_position();
+                Event::MousePress(MouseEvent {
+                    position: pos,
+                    button,
+                    modifiers: self.current_modifiers,
+                    click_count: self.clicks.press(pos, button),
+                })
             }
             winit::event::WindowEvent::MouseInput {
                 state: winit::event::ElementState::Released,
+                button,
                 ..
-            } => {
-                root.event(Event::MouseRelease(self.cursor_logical_position()));
+            } => Event::MouseRelease(MouseEvent {
+                position: self.cursor_logical_position(),
+                button,
+                modifiers: self.current_modifiers,
+                click_count: self.clicks.release(),
+            }),
+            winit::event::WindowEvent::MouseWheel { delta, .. } => {
+                let delta = match delta {
+                    winit::event::MouseScrollDelta::LineDelta(x, y) => ScrollDelta::Lines { x, y },
+                    winit::event::MouseScrollDelta::PixelDelta(pixels) => {
+                        let logical = pixels.to_logical(self.scale_factor);
+                        ScrollDelta::Pixels {
+                            x: logical.x,
+                            y: logical.y,
+                        }
+                    }
+                };
+                Event::Scroll(ScrollEvent {
+                    position: self.cursor_logical_position(),
+                    delta,
+                    modifiers: self.current_modifiers,
+                })
+            }
+            winit::event::WindowEvent::Focused(focused) => {
+                if !focused {
+                    self.captured = None;
+                    self.buttons_down = 0;
+                }
+                self.window_focus_changed(focused, root);
+                return;
+            }
+            winit::event::WindowEvent::KeyboardInput { input, .. } => {
+                let key_event = KeyEvent {
+                    scancode: input.scancode,
+                    key: input.virtual_keycode,
+                    modifiers: self.current_modifiers,
+                };
+                match input.state {
+                    winit::event::ElementState::Pressed => Event::KeyDown(key_event),
+                    winit::event::ElementState::Released => Event::KeyUp(key_event),
+                }
+            }
+            winit::event::WindowEvent::ReceivedCharacter(c) => Event::Character(c),
+            _ => return,
+        };
+        self.send_event(event, root);
+    }
+
+    /// Sends an event from the window to the widgets, keeping track of the hovered widget, the
+    /// widget capturing the pointer and the focused widget. A `MouseLeave` means the cursor left
+    /// the window, the other events which are only sent to a specific widget are ignored.
+    pub(crate) fn send_event(&mut self, event: Event, root: &mut WidgetTree) {
+        match event {
+            Event::MouseMove(pos) => {
+                self.update_hovered(root, Some(pos));
+                self.pointer_event(root, event);
+            }
+            Event::MouseLeave => self.update_hovered(root, None),
+            Event::MousePress(mouse_event) => {
+                if self.captured.is_none() {
+                    self.captured = root.widget_at(mouse_event.position);
+                }
+                self.buttons_down += 1;
+                self.pointer_event(root, event);
+            }
+            Event::MouseRelease(_) => {
+                self.pointer_event(root, event);
+                self.buttons_down = self.buttons_down.saturating_sub(1);
+                if self.buttons_down == 0 {
+                    self.captured = None;
+                }
+            }
+            Event::Scroll(_) | Event::KeyDown(_) | Event::KeyUp(_) | Event::Character(_) => {
+                root.event(event, &mut self.window);
+            }
+            Event::MouseEnter(_) | Event::FocusIn | Event::FocusOut => {}
+        }
+        self.apply_focus_change(root);
+    }
+
+    /// Sends the event to the widget capturing the pointer, otherwise to the widget under the
+    /// cursor.
+    fn pointer_event(&mut self, root: &mut WidgetTree, event: Event) {
+        if let Some(id) = self.captured {
+            if root.widget_event(id, event, &mut self.window) {
+                return;
+            }
+            self.captured = None;
+        }
+        root.event(event, &mut self.window);
+    }
+
+    /// Hit-tests the cursor position, sending leave and enter events when the widget under the
+    /// cursor is different from the last time.
+    fn update_hovered(&mut self, root: &mut WidgetTree, pos: Option<Position>) {
+        let hovered = pos.and_then(|pos| root.widget_at(pos));
+        if hovered == self.hovered {
+            return;
+        }
+        if let Some(old) = std::mem::replace(&mut self.hovered, hovered) {
+            root.widget_event(old, Event::MouseLeave, &mut self.window);
+        }
+        if let (Some(new), Some(pos)) = (hovered, pos) {
+            root.widget_event(new, Event::MouseEnter(pos), &mut self.window);
+        }
+    }
+
+    /// Moves focus to the widget that last requested it, notifying both the old and new owner.
+    /// Requests made while they're notified are ignored, otherwise widgets could pass focus back
+    /// and forth forever.
+    fn apply_focus_change(&mut self, root: &mut WidgetTree) {
+        let new_owner = match self.window.focus.requested.take() {
+            Some(new_owner) if new_owner != self.window.focus.owner => new_owner,
+            _ => return,
+        };
+        if let Some(old_owner) = std::mem::replace(&mut self.window.focus.owner, new_owner) {
+            root.widget_event(old_owner, Event::FocusOut, &mut self.window);
+        }
+        if let Some(new_owner) = new_owner {
+            if !root.widget_event(new_owner, Event::FocusIn, &mut self.window) {
+                self.window.focus.clear();
             }
-            _ => {}
         }
+        self.window.focus.requested = None;
+    }
+
+    /// The focus owner keeps focus while the window is in the background, but is sent `FocusOut`
+    /// and `FocusIn` as it loses and regains keyboard input along with the window.
+    fn window_focus_changed(&mut self, focused: bool, root: &mut WidgetTree) {
+        if let Some(owner) = self.window.focus.owner {
+            let event = if focused {
+                Event::FocusIn
+            } else {
+                Event::FocusOut
+            };
+            if !root.widget_event(owner, event, &mut self.window) {
+                self.window.focus.clear();
+            }
+        }
+        // As for focus changes, see `apply_focus_change`
+        self.window.focus.requested = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geom::{Size, SizeHint};
+    use crate::headless::testing::test_window;
+    use crate::renderer::painter::Painter;
+    use crate::view::{ViewRefs, Widget, WidgetKey, WidgetState, WidgetStateMut, WidgetTree};
+    use crate::view_model::ViewModel;
+    use crate::{view, widgets, Description};
+
+    #[derive(ViewModel)]
+    struct Model {
+        view_refs: ViewRefs,
+        // The id of each widget gaining focus, in order
+        focused: Vec<u32>,
+    }
+
+    /// Takes focus when pressed, and tries to take it back whenever it loses it.
+    #[derive(Description)]
+    #[description(widget = "GrabbyWidget", model = "Model")]
+    struct Grabby {
+        id: u32,
+        key: WidgetKey,
+    }
+
+    impl Grabby {
+        #[track_caller]
+        fn new(id: u32) -> Grabby {
+            Grabby {
+                id,
+                key: WidgetKey::caller_with(id),
+            }
+        }
+    }
+
+    struct GrabbyWidget {
+        id: u32,
+    }
+
+    impl From<Grabby> for GrabbyWidget {
+        fn from(grabby: Grabby) -> Self {
+            GrabbyWidget { id: grabby.id }
+        }
+    }
+
+    impl Widget<Model> for GrabbyWidget {
+        fn event(&mut self, mut state: WidgetStateMut<'_, Model>, event: Event) -> EventResult {
+            match event {
+                Event::MousePress(_) | Event::FocusOut => state.request_focus(),
+                Event::FocusIn => state.model_mut().focused.push(self.id),
+                _ => return EventResult::Unhandled,
+            }
+            EventResult::Handled
+        }
+
+        fn paint(&self, _: WidgetState<'_, Model>, _: &mut Painter) {}
+
+        fn size_hint(&self, _: &[WidgetTree]) -> SizeHint {
+            SizeHint::new(Size::zero(), Size::new(10.0, 10.0), Size::infinite())
+        }
+    }
+
+    #[test]
+    fn focus_requests_while_losing_focus_are_ignored() {
+        let model = Model {
+            view_refs: ViewRefs::new(),
+            focused: Vec::new(),
+        };
+        let mut window = test_window(model, |cache, _| {
+            cache.build(view! {
+                widgets::Box {
+                    Grabby(1)
+                    Grabby(2)
+                }
+            })
+        });
+        let first = window.rects_with_key(1u32)[0];
+        let second = window.rects_with_key(2u32)[0];
+        window.click(first.center());
+        window.click(second.center());
+        assert_eq!(window.model_mut().focused, vec![1, 2]);
     }
 }
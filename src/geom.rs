@@ -69,6 +69,13 @@ impl Size {
     }
 }
 
+impl Size {
+    /// A size which is larger than any other size.
+    pub fn infinite() -> Size {
+        Size::new(Scalar::INFINITY, Scalar::INFINITY)
+    }
+}
+
 impl AddAssign for Size {
     fn add_assign(&mut self, rhs: Self) {
         self.width += rhs.width;
@@ -82,6 +89,60 @@ impl<P: winit::dpi::Pixel> From<winit::dpi::LogicalSize<P>> for Size {
     }
 }
 
+/// The sizes a widget can be, reported by the widget to its parent.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SizeHint {
+    pub min: Size,
+    pub preferred: Size,
+    pub max: Size,
+}
+
+impl SizeHint {
+    pub fn new(min: Size, preferred: Size, max: Size) -> SizeHint {
+        SizeHint {
+            min,
+            preferred,
+            max,
+        }
+    }
+
+    /// A widget that can only be the given size.
+    pub fn fixed(size: Size) -> SizeHint {
+        SizeHint::new(size, size, size)
+    }
+}
+
+/// The sizes a parent allows its child to be.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Constraints {
+    pub min: Size,
+    pub max: Size,
+}
+
+impl Constraints {
+    pub fn new(min: Size, max: Size) -> Constraints {
+        Constraints { min, max }
+    }
+
+    /// Only allows the given size.
+    pub fn tight(size: Size) -> Constraints {
+        Constraints::new(size, size)
+    }
+
+    /// Allows any size up to the given size.
+    pub fn loose(max: Size) -> Constraints {
+        Constraints::new(Size::zero(), max)
+    }
+
+    /// The closest size to the given size which satisfies the constraints.
+    pub fn constrain(&self, size: Size) -> Size {
+        Size::new(
+            size.width.max(self.min.width).min(self.max.width),
+            size.height.max(self.min.height).min(self.max.height),
+        )
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub struct Rect {
     pub origin: Position,
@@ -106,6 +167,31 @@ impl Rect {
         Rect::new(Position::new(self.origin.x + dx, self.origin.y + dy), size)
     }
 
+    /// The overlapping area of both rects, if there is any.
+    pub fn intersection(&self, other: Rect) -> Option<Rect> {
+        let x = self.origin.x.max(other.origin.x);
+        let y = self.origin.y.max(other.origin.y);
+        let right = (self.origin.x + self.size.width).min(other.origin.x + other.size.width);
+        let bottom = (self.origin.y + self.size.height).min(other.origin.y + other.size.height);
+        if x < right && y < bottom {
+            Some(Rect::new(
+                Position::new(x, y),
+                Size::new(right - x, bottom - y),
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// The smallest rect containing both rects.
+    pub fn union(&self, other: Rect) -> Rect {
+        let x = self.origin.x.min(other.origin.x);
+        let y = self.origin.y.min(other.origin.y);
+        let right = (self.origin.x + self.size.width).max(other.origin.x + other.size.width);
+        let bottom = (self.origin.y + self.size.height).max(other.origin.y + other.size.height);
+        Rect::new(Position::new(x, y), Size::new(right - x, bottom - y))
+    }
+
     pub fn center(&self) -> Position {
         Position::new(
             self.origin.x + self.size.width / 2.0,
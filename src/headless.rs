@@ -0,0 +1,275 @@
+//! Runs views without a window, event loop or GPU, so that they can be tested.
+
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use crate::app::AppInner;
+use crate::events::{Event, EventState, ModifiersState, MouseButton, MouseEvent};
+use crate::geom::{Constraints, Position, Rect, Size};
+use crate::renderer::display_list::DisplayList;
+use crate::renderer::offscreen::{self, Image};
+use crate::view::{KeyValue, LayoutContext, View, WidgetTree, WidgetTreeFactory};
+
+/// A window which is only in memory. Events are sent to it directly, updates from view models are
+/// processed when asked for, and painting records what would be painted. `M` is the model of the
+/// root view.
+pub struct HeadlessWindow<M> {
+    app: Arc<AppInner>,
+    root: WidgetTree,
+    events: EventState,
+    size: Size,
+    model: PhantomData<fn(&mut M)>,
+}
+
+impl<M: 'static> HeadlessWindow<M> {
+    /// Builds the view from its model, and lays it out at the given logical size.
+    pub fn new<V: View<Model = M> + 'static>(root: V, model: M, size: Size) -> HeadlessWindow<M> {
+        let app = Arc::new(AppInner::new(None));
+        let factory = WidgetTreeFactory::<()>::new(Arc::clone(&app));
+        let mut root = factory.new_view(root, model);
+        root.materialise_views();
+        let mut window = HeadlessWindow {
+            app,
+            root,
+            events: EventState::new(1.0),
+            size,
+            model: PhantomData,
+        };
+        window.layout();
+        window
+    }
+
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    pub fn resize(&mut self, size: Size) {
+        self.size = size;
+        self.layout();
+    }
+
+    fn layout(&mut self) {
+        let rect = self.root.layout(LayoutContext::new(
+            Position::zero(),
+            Constraints::tight(self.size),
+        ));
+        self.events.damage().add(rect);
+    }
+
+    /// Sends the event as if it came from the window, positions are in logical pixels. Changes to
+    /// view models made while handling the event are applied by `update`.
+    pub fn send_event(&mut self, event: Event) {
+        self.events.send_event(event, &mut self.root);
+    }
+
+    /// Moves the mouse to the position, then presses and releases the left button.
+    pub fn click(&mut self, pos: Position) {
+        let mouse_event = MouseEvent {
+            position: pos,
+            button: MouseButton::Left,
+            modifiers: ModifiersState::empty(),
+            click_count: 1,
+        };
+        self.send_event(Event::MouseMove(pos));
+        self.send_event(Event::MousePress(mouse_event));
+        self.send_event(Event::MouseRelease(mouse_event));
+    }
+
+    /// Rebuilds the views and updates the widgets whose view models changed, as the app does once
+    /// it has handled a window's events. Returns false if there was nothing to update.
+    pub fn update(&mut self) -> bool {
+        let (views, widgets) = self.app.take_updates();
+        if views.is_empty() && widgets.is_empty() {
+            return false;
+        }
+        if self.root.update(&views, &widgets, self.events.damage()) {
+            self.layout();
+        }
+        true
+    }
+
+    /// The model of the root view.
+    pub fn model_mut(&mut self) -> &mut M {
+        self.root
+            .view_model_mut()
+            .and_then(|model| model.downcast_mut())
+            .expect("Root is a view of an M")
+    }
+
+    /// The rects of the widgets and layouts whose keys have the given value, see
+    /// `WidgetKey::with_value`, in the order they're in the tree.
+    pub fn rects_with_key(&self, value: impl Into<KeyValue>) -> Vec<Rect> {
+        let value = value.into();
+        let mut rects = Vec::new();
+        self.root.visit_keyed(&mut |key, rect| {
+            if key.value() == Some(&value) {
+                rects.push(rect);
+            }
+        });
+        rects
+    }
+
+    /// The rect of the whole tree, which fills the window unless the root can't fit.
+    pub fn root_rect(&self) -> Rect {
+        self.root.rect()
+    }
+
+    /// Paints the whole window, returning what was painted. Replaying the list with a
+    /// `Vec<PaintCommand>` gives where everything ends up in the window.
+    pub fn paint(&mut self) -> DisplayList {
+        self.events.damage().take();
+        let root = &self.root;
+        DisplayList::record(self.size, |painter| root.paint(None, painter))
+    }
+
+    /// Paints the whole window on the CPU, into an image of its size in physical pixels, e.g. for
+    /// snapshot tests or screenshots.
+    pub fn render(&mut self, scale_factor: f64) -> Image {
+        self.events.damage().take();
+        offscreen::paint_tree(&self.root, self.size, scale_factor)
+    }
+}
+
+/// Helpers for testing views with headless windows.
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::marker::PhantomData;
+
+    use super::HeadlessWindow;
+    use crate::geom::Size;
+    use crate::view::{View, WidgetCache, WidgetTree};
+    use crate::view_model::ViewModel;
+
+    /// A view built by a function, so that tests can declare their views inline.
+    struct FnView<M, F> {
+        view: F,
+        model: PhantomData<fn(&mut M)>,
+    }
+
+    impl<M, F> View for FnView<M, F>
+    where
+        M: ViewModel + 'static,
+        F: FnMut(&mut WidgetCache<M>, &M) -> WidgetTree,
+    {
+        type Model = M;
+
+        fn view(&mut self, cache: &mut WidgetCache<M>, model: &M) -> WidgetTree {
+            (self.view)(cache, model)
+        }
+    }
+
+    /// A window whose root view is built by the function.
+    pub(crate) fn test_window<M: ViewModel + 'static>(
+        model: M,
+        view: impl FnMut(&mut WidgetCache<M>, &M) -> WidgetTree + 'static,
+    ) -> HeadlessWindow<M> {
+        let view = FnView {
+            view,
+            model: PhantomData,
+        };
+        HeadlessWindow::new(view, model, Size::new(200.0, 600.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glyph_brush::Text;
+
+    use super::testing::test_window;
+    use super::*;
+    use crate::view::ViewRefs;
+    use crate::view_model::{Observable, ViewModel};
+    use crate::{view, widgets, PaintCommand};
+
+    const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+    const BLUE: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+
+    #[derive(ViewModel)]
+    struct Counter {
+        view_refs: ViewRefs,
+        count: Observable<u64>,
+    }
+
+    /// A window with `count` rows, below a button which adds a row each time it's pressed.
+    fn window(count: u64) -> HeadlessWindow<Counter> {
+        let model = Counter {
+            view_refs: ViewRefs::new(),
+            count: Observable::new(count),
+        };
+        test_window(model, |cache, model: &Counter| {
+            let count = model.count.get();
+            cache.build(view! {
+                widgets::Box {
+                    widgets::Button(
+                        RED,
+                        |model: &mut Counter| model.count.modify(|count| *count += 1),
+                        key = "add",
+                        add_text = &Text::new("Add"),
+                    )
+                    for row in 0..count {
+                        widgets::Button(BLUE, |_: &mut Counter| {}, key = row)
+                    }
+                }
+            })
+        })
+    }
+
+    #[test]
+    fn click_updates_model_and_rebuilds() {
+        let mut window = window(0);
+        assert!(!window.update());
+        let add = window.rects_with_key("add")[0];
+        window.click(add.center());
+        assert_eq!(window.model_mut().count.get(), 1);
+        assert!(window.update());
+        assert_eq!(window.rects_with_key(0u64).len(), 1);
+        assert!(!window.update());
+    }
+
+    #[test]
+    fn model_changes_rebuild() {
+        let mut window = window(2);
+        window.model_mut().count.set(0);
+        assert!(window.update());
+        assert!(window.rects_with_key(0u64).is_empty());
+        assert!(window.rects_with_key(1u64).is_empty());
+    }
+
+    #[test]
+    fn rects_with_key_are_laid_out_in_order() {
+        let window = window(2);
+        let add = window.rects_with_key("add");
+        let first = window.rects_with_key(0u64);
+        let second = window.rects_with_key(1u64);
+        assert_eq!((add.len(), first.len(), second.len()), (1, 1, 1));
+        assert!(add[0].origin.y < first[0].origin.y);
+        assert!(first[0].origin.y < second[0].origin.y);
+        assert!(window.root_rect().contains(second[0].origin));
+        assert!(window.rects_with_key("missing").is_empty());
+    }
+
+    #[test]
+    fn paint_records_each_widget() {
+        let mut window = window(1);
+        let mut commands: Vec<PaintCommand> = Vec::new();
+        window.paint().replay(&mut commands);
+        let quads = commands
+            .iter()
+            .filter_map(|command| match command {
+                PaintCommand::Quad { rect, colour } => Some((*rect, *colour)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            quads,
+            vec![
+                (window.rects_with_key("add")[0], RED),
+                (window.rects_with_key(0u64)[0], BLUE),
+            ]
+        );
+        assert!(commands.iter().any(|command| matches!(
+            command,
+            PaintCommand::Text { text, .. } if text == "Add"
+        )));
+    }
+}
@@ -1,10 +1,18 @@
+// Lets the derive macros, which refer to ::kakapo, be used within this crate
+extern crate self as kakapo;
+
 pub mod app;
 mod description;
-mod events;
+pub mod events;
 pub mod geom;
-mod renderer;
+pub mod headless;
+pub mod renderer;
+pub mod snapshot;
 pub mod view;
 pub mod view_model;
 pub mod widgets;
 
-pub use description::Description;
+pub use description::{BoxedDescription, Description};
+pub use kakapo_derive::view;
+pub use renderer::display_list::{DisplayItem, DisplayList};
+pub use renderer::painter::{PaintCommand, Painter};
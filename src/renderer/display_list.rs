@@ -0,0 +1,165 @@
+//! What's painted, recorded as a list of items which backends then paint.
+
+use std::fmt;
+
+use glyph_brush::OwnedSection;
+
+use crate::geom::{Position, Rect, Size};
+use crate::renderer::painter::Painter;
+use crate::renderer::PaintBackend;
+
+/// An item of a display list. Positions are relative to the enclosing transforms, and each push
+/// is matched by a pop later in the list.
+#[derive(Clone, PartialEq, Debug)]
+pub enum DisplayItem {
+    Quad {
+        rect: Rect,
+        colour: [f32; 4],
+    },
+    Text(OwnedSection),
+    /// Clips the items until the matching `PopClip` to the rect, within any enclosing clips.
+    PushClip(Rect),
+    PopClip,
+    /// Moves the items until the matching `PopTransform` by the offset.
+    PushTransform(Position),
+    PopTransform,
+    /// Groups the items until the matching `PopLayer`, so that they're composited together with
+    /// the opacity, from 0 to 1.
+    PushLayer(f32),
+    PopLayer,
+}
+
+/// The items painted into a viewport of the given logical size, in the order they were painted.
+#[derive(Clone, PartialEq, Debug)]
+pub struct DisplayList {
+    size: Size,
+    items: Vec<DisplayItem>,
+}
+
+impl DisplayList {
+    /// Records the items painted by the function.
+    pub fn record(size: Size, paint_func: impl FnOnce(&mut Painter<'_>)) -> DisplayList {
+        let mut items = Vec::new();
+        paint_func(&mut Painter::new(&mut items));
+        DisplayList { size, items }
+    }
+
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    pub fn items(&self) -> &[DisplayItem] {
+        &self.items
+    }
+
+    /// Paints the items with the backend, applying the transforms and clips so that the backend
+    /// is given positions relative to the viewport. Layers are left to the backend.
+    pub fn replay(&self, backend: &mut dyn PaintBackend) {
+        let mut origin = Position::zero();
+        let mut origins = Vec::new();
+        // None when everything would be clipped
+        let mut clip = Some(Rect::new(Position::zero(), self.size));
+        let mut clips = Vec::new();
+        for item in self.items.iter() {
+            match item {
+                DisplayItem::Quad { rect, colour } => {
+                    // Quads are a solid colour, so clipping is just shrinking the quad
+                    let rect = Rect::new(rect.origin + origin, rect.size);
+                    if let Some(rect) = clip.and_then(|clip| clip.intersection(rect)) {
+                        backend.paint_quad(rect, *colour);
+                    }
+                }
+                DisplayItem::Text(section) => {
+                    if let Some(clip) = clip {
+                        let mut section = section.to_borrowed();
+                        section.screen_position = (
+                            section.screen_position.0 + origin.x,
+                            section.screen_position.1 + origin.y,
+                        );
+                        backend.paint_text(section, clip);
+                    }
+                }
+                DisplayItem::PushClip(rect) => {
+                    clips.push(clip);
+                    let rect = Rect::new(rect.origin + origin, rect.size);
+                    clip = clip.and_then(|clip| clip.intersection(rect));
+                }
+                DisplayItem::PopClip => clip = clips.pop().expect("Unmatched PopClip"),
+                DisplayItem::PushTransform(offset) => {
+                    origins.push(origin);
+                    origin += *offset;
+                }
+                DisplayItem::PopTransform => {
+                    origin = origins.pop().expect("Unmatched PopTransform")
+                }
+                DisplayItem::PushLayer(opacity) => backend.push_layer(*opacity),
+                DisplayItem::PopLayer => backend.pop_layer(),
+            }
+        }
+    }
+
+    /// How many quads are in the list, before any are clipped.
+    pub(crate) fn quad_count(&self) -> usize {
+        self.items
+            .iter()
+            .filter(|item| matches!(item, DisplayItem::Quad { .. }))
+            .count()
+    }
+}
+
+/// Lists the items one per line, with the items within each push indented, so that lists can be
+/// read and compared as text.
+impl fmt::Display for DisplayList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut depth = 0;
+        for item in self.items.iter() {
+            if let DisplayItem::PopClip | DisplayItem::PopTransform | DisplayItem::PopLayer = item {
+                depth -= 1;
+                writeln!(f, "{:indent$}}}", "", indent = depth * 2)?;
+                continue;
+            }
+            write!(f, "{:indent$}", "", indent = depth * 2)?;
+            match item {
+                DisplayItem::Quad { rect, colour } => {
+                    writeln!(f, "quad {} {:?}", DisplayRect(*rect), colour)?
+                }
+                DisplayItem::Text(section) => {
+                    let text = section
+                        .text
+                        .iter()
+                        .map(|text| text.text.as_str())
+                        .collect::<String>();
+                    let (x, y) = section.screen_position;
+                    writeln!(f, "text {:?} at {},{}", text, x, y)?
+                }
+                DisplayItem::PushClip(rect) => {
+                    writeln!(f, "clip {} {{", DisplayRect(*rect))?;
+                    depth += 1;
+                }
+                DisplayItem::PushTransform(offset) => {
+                    writeln!(f, "transform {},{} {{", offset.x, offset.y)?;
+                    depth += 1;
+                }
+                DisplayItem::PushLayer(opacity) => {
+                    writeln!(f, "layer {} {{", opacity)?;
+                    depth += 1;
+                }
+                DisplayItem::PopClip | DisplayItem::PopTransform | DisplayItem::PopLayer => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+struct DisplayRect(Rect);
+
+impl fmt::Display for DisplayRect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Rect { origin, size } = self.0;
+        write!(
+            f,
+            "{},{} {}x{}",
+            origin.x, origin.y, size.width, size.height
+        )
+    }
+}
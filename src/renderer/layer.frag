@@ -0,0 +1,15 @@
+#version 450
+
+layout(set=0, binding=0) uniform texture2D t_layer;
+layout(set=0, binding=1) uniform sampler s_layer;
+layout(set=0, binding=2) uniform Layer {
+    float opacity;
+};
+
+layout(location=0) out vec4 f_color;
+
+void main() {
+    // The layer is the same size as the target, and its colours are already premultiplied
+    vec4 colour = texelFetch(sampler2D(t_layer, s_layer), ivec2(gl_FragCoord.xy), 0);
+    f_color = colour * opacity;
+}
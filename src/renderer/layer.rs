@@ -0,0 +1,198 @@
+use wgpu::util::DeviceExt;
+
+/// Composites layers, painted into textures the size of the frame, onto what's beneath them.
+pub(super) struct LayerPipeline {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    format: wgpu::TextureFormat,
+    // A texture for each depth of nested layers painted so far, all of `size`
+    textures: Vec<(wgpu::Texture, wgpu::TextureView)>,
+    size: winit::dpi::PhysicalSize<u32>,
+}
+
+/// Blends premultiplied colours over what's already been drawn, the same as quads.
+const PREMULTIPLIED_BLEND: wgpu::BlendDescriptor = wgpu::BlendDescriptor {
+    src_factor: wgpu::BlendFactor::One,
+    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+    operation: wgpu::BlendOperation::Add,
+};
+
+impl LayerPipeline {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> LayerPipeline {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Layer Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture {
+                        dimension: wgpu::TextureViewDimension::D2,
+                        component_type: wgpu::TextureComponentType::Float,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler { comparison: false },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Layer Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let vs_module = shader!(device, "layer.vert.spv");
+        let fs_module = shader!(device, "layer.frag.spv");
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Layer Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: &fs_module,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Cw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+                clamp_depth: false,
+            }),
+            color_states: &[wgpu::ColorStateDescriptor {
+                format,
+                color_blend: PREMULTIPLIED_BLEND,
+                alpha_blend: PREMULTIPLIED_BLEND,
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
+            depth_stencil_state: None,
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint16,
+                vertex_buffers: &[],
+            },
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Layer Sampler"),
+            ..Default::default()
+        });
+
+        LayerPipeline {
+            pipeline,
+            bind_group_layout,
+            sampler,
+            format,
+            textures: Vec::new(),
+            size: winit::dpi::PhysicalSize::new(0, 0),
+        }
+    }
+
+    /// Makes sure there's a texture of the size for the layer at the depth, starting from 0 for
+    /// the outermost layer.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        depth: usize,
+        size: winit::dpi::PhysicalSize<u32>,
+    ) {
+        if size != self.size {
+            self.textures.clear();
+            self.size = size;
+        }
+        while self.textures.len() <= depth {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("Layer"),
+                size: wgpu::Extent3d {
+                    width: size.width,
+                    height: size.height,
+                    depth: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: self.format,
+                usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            self.textures.push((texture, view));
+        }
+    }
+
+    /// The texture of the layer at the depth, see `prepare`.
+    pub fn view(&self, depth: usize) -> &wgpu::TextureView {
+        &self.textures[depth].1
+    }
+
+    /// Composites the layer at the depth onto the target with the opacity.
+    pub fn composite(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        depth: usize,
+        target: &wgpu::TextureView,
+        opacity: f32,
+    ) {
+        // Padded to the size of a vec4, as uniform blocks are
+        let uniform = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Layer Opacity"),
+            contents: bytemuck::cast_slice(&[opacity, 0.0, 0.0, 0.0]),
+            usage: wgpu::BufferUsage::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Layer Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(self.view(depth)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Buffer(uniform.slice(..)),
+                },
+            ],
+        });
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..4, 0..1);
+    }
+}
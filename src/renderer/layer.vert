@@ -0,0 +1,7 @@
+#version 450
+
+void main() {
+    // A triangle strip covering the whole viewport
+    vec2 corner = vec2(gl_VertexIndex & 1, gl_VertexIndex >> 1);
+    gl_Position = vec4(corner * 2.0 - 1.0, 0.0, 1.0);
+}
@@ -1,17 +1,122 @@
+use std::ops::Range;
+
 use futures::executor::LocalPool;
 use futures::task::LocalSpawnExt;
 use wgpu::util::StagingBelt;
 use winit::window::Window;
 
-use crate::renderer::painter::Painter;
+use crate::geom::{Position, Rect, Size};
+use crate::renderer::display_list::DisplayList;
+use crate::renderer::layer::LayerPipeline;
 use crate::renderer::quad::QuadPipeline;
+use crate::renderer::software::SoftwareRenderer;
 use crate::renderer::text::TextPipeline;
+use crate::renderer::x11::XlibSurface;
+
+macro_rules! shader {
+    ($device:expr, $spv:tt) => {
+        $device.create_shader_module(wgpu::include_spirv!(concat!(env!("OUT_DIR"), "/", $spv)))
+    };
+}
 
+pub mod display_list;
+mod layer;
+pub mod offscreen;
 pub mod painter;
 mod quad;
+pub mod software;
 mod text;
+mod x11;
+
+/// What a `DisplayList` is painted with. Rects and positions are in logical pixels relative to the
+/// viewport, and have already been clipped, except for text which is clipped by the backend.
+/// Colours have straight, not premultiplied, alpha.
+pub trait PaintBackend {
+    fn paint_quad(&mut self, rect: Rect, colour: [f32; 4]);
+
+    fn paint_text(&mut self, section: wgpu_glyph::Section<'_>, clip: Rect);
+
+    /// Starts a layer. Everything painted until the matching `pop_layer` is painted into the
+    /// layer, which is then composited onto what's beneath it with the opacity.
+    fn push_layer(&mut self, opacity: f32);
+
+    fn pop_layer(&mut self);
+}
+
+fn default_font() -> wgpu_glyph::ab_glyph::FontArc {
+    wgpu_glyph::ab_glyph::FontArc::try_from_slice(include_bytes!("Roboto-Regular.ttf")).unwrap()
+}
+
+/// Scales the section from logical to physical pixels, its position and bounds along with the size
+/// of its text.
+fn scale_section(section: &mut wgpu_glyph::Section<'_>, scale: f32) {
+    section.screen_position = (
+        section.screen_position.0 * scale,
+        section.screen_position.1 * scale,
+    );
+    section.bounds = (section.bounds.0 * scale, section.bounds.1 * scale);
+    for text in section.text.iter_mut() {
+        text.scale.x *= scale;
+        text.scale.y *= scale;
+    }
+}
+
+/// Paints a window, on the GPU when there's a suitable adapter and otherwise on the CPU. Setting
+/// `KAKAPO_SOFTWARE_RENDERING` always paints on the CPU.
+pub(crate) enum WindowRenderer {
+    Gpu(Box<Renderer>),
+    Software {
+        renderer: SoftwareRenderer,
+        surface: XlibSurface,
+    },
+}
+
+impl WindowRenderer {
+    pub async fn new(window: &Window) -> Self {
+        if std::env::var_os("KAKAPO_SOFTWARE_RENDERING").is_none() {
+            if let Some(renderer) = Renderer::new(window).await {
+                return WindowRenderer::Gpu(Box::new(renderer));
+            }
+        }
+        let size = window.inner_size();
+        WindowRenderer::Software {
+            renderer: SoftwareRenderer::new(size.width, size.height),
+            surface: XlibSurface::new(window).expect("Failed to paint to window with X11"),
+        }
+    }
+
+    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        match self {
+            WindowRenderer::Gpu(renderer) => renderer.resize(new_size),
+            WindowRenderer::Software { renderer, .. } => {
+                renderer.resize(new_size.width, new_size.height)
+            }
+        }
+    }
+
+    pub fn recreate(&mut self) {
+        if let WindowRenderer::Gpu(renderer) = self {
+            renderer.recreate();
+        }
+    }
+
+    pub fn render(
+        &mut self,
+        scale_factor: f64,
+        display_list: &DisplayList,
+    ) -> Result<(), wgpu::SwapChainError> {
+        match self {
+            WindowRenderer::Gpu(renderer) => renderer.render(scale_factor, display_list),
+            WindowRenderer::Software { renderer, surface } => {
+                renderer.render(scale_factor, display_list);
+                surface.present(renderer);
+                Ok(())
+            }
+        }
+    }
+}
 
-pub struct Renderer {
+pub(crate) struct Renderer {
     surface: wgpu::Surface,
     device: wgpu::Device,
     queue: wgpu::Queue,
@@ -21,12 +126,14 @@ pub struct Renderer {
 
     quad: quad::QuadPipeline,
     text: text::TextPipeline,
+    layer: layer::LayerPipeline,
     belt: StagingBelt,
     pool: LocalPool,
 }
 
 impl Renderer {
-    pub async fn new(window: &Window) -> Self {
+    /// None if there's no Vulkan adapter which can paint to the window.
+    pub async fn new(window: &Window) -> Option<Self> {
         let size = window.inner_size();
 
         let instance = wgpu::Instance::new(wgpu::BackendBit::VULKAN);
@@ -36,8 +143,7 @@ impl Renderer {
                 power_preference: wgpu::PowerPreference::Default,
                 compatible_surface: Some(&surface),
             })
-            .await
-            .unwrap();
+            .await?;
 
         let (device, queue) = adapter
             .request_device(
@@ -49,7 +155,7 @@ impl Renderer {
                 None, // Trace path
             )
             .await
-            .unwrap();
+            .ok()?;
 
         let sc_desc = wgpu::SwapChainDescriptor {
             usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
@@ -62,8 +168,9 @@ impl Renderer {
 
         let quad = QuadPipeline::new(&device, sc_desc.format);
         let text = TextPipeline::new(&device, sc_desc.format);
+        let layer = LayerPipeline::new(&device, sc_desc.format);
 
-        Self {
+        Some(Self {
             surface,
             device,
             queue,
@@ -72,9 +179,10 @@ impl Renderer {
             size,
             quad,
             text,
+            layer,
             belt: StagingBelt::new(0x100),
             pool: LocalPool::new(),
-        }
+        })
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -88,10 +196,10 @@ impl Renderer {
         self.swap_chain = self.device.create_swap_chain(&self.surface, &self.sc_desc);
     }
 
-    pub fn render<F: for<'a> FnOnce(&'a mut Painter<'a>)>(
+    pub fn render(
         &mut self,
         scale_factor: f64,
-        render_func: F,
+        display_list: &DisplayList,
     ) -> Result<(), wgpu::SwapChainError> {
         let frame = self.swap_chain.get_current_frame()?.output;
 
@@ -101,40 +209,20 @@ impl Renderer {
                 label: Some("Render Encoder"),
             });
 
-        render_func(&mut Painter::new(
-            self,
-            &mut encoder,
-            self.size.to_logical::<f32>(scale_factor).into(),
-            scale_factor,
-        ));
-
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                    attachment: &frame.view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    },
-                }],
-                depth_stencil_attachment: None,
-            });
-            self.quad.record(&mut render_pass);
-        }
-
-        self.text.record(
-            &self.device,
-            &mut self.belt,
-            &mut encoder,
-            &frame,
-            self.size,
-        );
+        self.quad.reset(&self.device, display_list.quad_count());
+        let viewport_size = self.size.to_logical::<f32>(scale_factor).into();
+        let mut gpu_frame = GpuFrame {
+            renderer: self,
+            encoder: &mut encoder,
+            frame: &frame.view,
+            viewport_size,
+            scale: scale_factor as f32,
+            passes: FramePasses::default(),
+        };
+        display_list.replay(&mut gpu_frame);
+        // Also clears the frame when nothing was painted
+        let pass = gpu_frame.passes.draw();
+        gpu_frame.run(pass);
 
         self.belt.finish();
 
@@ -146,3 +234,294 @@ impl Renderer {
         Ok(())
     }
 }
+
+/// Where a pass is drawn.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum PassTarget {
+    Frame,
+    /// The texture of the layer at the depth, starting from 0 for the outermost layer.
+    Layer(usize),
+}
+
+impl PassTarget {
+    fn view<'a>(
+        self,
+        frame: &'a wgpu::TextureView,
+        layer: &'a LayerPipeline,
+    ) -> &'a wgpu::TextureView {
+        match self {
+            PassTarget::Frame => frame,
+            PassTarget::Layer(depth) => layer.view(depth),
+        }
+    }
+}
+
+/// A render pass of a frame.
+#[derive(Clone, PartialEq, Debug)]
+enum Pass {
+    /// Draws the quads, then the text added since the previous draw, clearing the target first
+    /// when `clear` is set.
+    Draw {
+        target: PassTarget,
+        clear: bool,
+        quads: Range<u32>,
+    },
+    /// Composites the texture of the layer at the depth onto the target with the opacity.
+    Composite {
+        layer: usize,
+        target: PassTarget,
+        opacity: f32,
+    },
+}
+
+/// Plans the passes a frame is drawn with, as it's painted.
+///
+/// What's painted is drawn in batches, into the frame or into the texture of the innermost layer.
+/// Text is drawn over the quads of its batch, so each layer starts and ends a batch. When a layer
+/// ends its texture is composited with its opacity onto what's beneath it.
+#[derive(Default)]
+struct FramePasses {
+    quad_count: u32,
+    // The first quad of the current batch
+    batch_start: u32,
+    // Whether what the current batch is drawn into has been cleared
+    cleared: bool,
+    // The opacity of each layer being painted, the innermost last
+    layers: Vec<f32>,
+}
+
+impl FramePasses {
+    fn add_quad(&mut self) {
+        self.quad_count += 1;
+    }
+
+    fn target(&self) -> PassTarget {
+        match self.layers.len() {
+            0 => PassTarget::Frame,
+            depth => PassTarget::Layer(depth - 1),
+        }
+    }
+
+    /// Ends the current batch.
+    fn draw(&mut self) -> Pass {
+        let pass = Pass::Draw {
+            target: self.target(),
+            clear: !self.cleared,
+            quads: self.batch_start..self.quad_count,
+        };
+        self.batch_start = self.quad_count;
+        self.cleared = true;
+        pass
+    }
+
+    /// Draws what's beneath the layer, which also clears the target beneath it. The layer's
+    /// texture is at the depth `layers.len() - 1` afterwards.
+    fn push_layer(&mut self, opacity: f32) -> Pass {
+        let pass = self.draw();
+        self.layers.push(opacity);
+        self.cleared = false;
+        pass
+    }
+
+    fn pop_layer(&mut self) -> [Pass; 2] {
+        let draw = self.draw();
+        let opacity = self.layers.pop().expect("Unmatched pop_layer");
+        let composite = Pass::Composite {
+            layer: self.layers.len(),
+            target: self.target(),
+            opacity,
+        };
+        [draw, composite]
+    }
+}
+
+/// Paints a frame with a `Renderer`, in the passes planned by `FramePasses`.
+struct GpuFrame<'a> {
+    renderer: &'a mut Renderer,
+    encoder: &'a mut wgpu::CommandEncoder,
+    frame: &'a wgpu::TextureView,
+    viewport_size: Size,
+    scale: f32,
+    passes: FramePasses,
+}
+
+impl GpuFrame<'_> {
+    /// Transforms the rect from viewport space into Vulkan coordinate space, where the viewport
+    /// goes from -1 to 1.
+    fn transform_rect(&self, rect: Rect) -> Rect {
+        Rect::new(
+            Position::new(
+                2.0 * rect.origin.x / self.viewport_size.width - 1.0,
+                1.0 - 2.0 * (rect.origin.y + rect.size.height) / self.viewport_size.height,
+            ),
+            Size::new(
+                2.0 * rect.size.width / self.viewport_size.width,
+                2.0 * rect.size.height / self.viewport_size.height,
+            ),
+        )
+    }
+
+    fn run(&mut self, pass: Pass) {
+        let renderer = &mut *self.renderer;
+        match pass {
+            Pass::Draw {
+                target,
+                clear,
+                quads,
+            } => {
+                let load = match (clear, target) {
+                    (false, _) => wgpu::LoadOp::Load,
+                    (true, PassTarget::Frame) => wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    (true, PassTarget::Layer(_)) => wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                };
+                let target = target.view(self.frame, &renderer.layer);
+                {
+                    let mut render_pass =
+                        self.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                                attachment: target,
+                                resolve_target: None,
+                                ops: wgpu::Operations { load, store: true },
+                            }],
+                            depth_stencil_attachment: None,
+                        });
+                    renderer.quad.record(&mut render_pass, quads);
+                }
+                renderer.text.record(
+                    &renderer.device,
+                    &mut renderer.belt,
+                    self.encoder,
+                    target,
+                    renderer.size,
+                );
+            }
+            Pass::Composite {
+                layer,
+                target,
+                opacity,
+            } => {
+                renderer.layer.composite(
+                    &renderer.device,
+                    self.encoder,
+                    layer,
+                    target.view(self.frame, &renderer.layer),
+                    opacity,
+                );
+            }
+        }
+    }
+}
+
+impl PaintBackend for GpuFrame<'_> {
+    fn paint_quad(&mut self, rect: Rect, colour: [f32; 4]) {
+        let transformed_rect = self.transform_rect(rect);
+        let renderer = &mut *self.renderer;
+        renderer.quad.add_quad(
+            &mut renderer.belt,
+            self.encoder,
+            &renderer.device,
+            transformed_rect,
+            colour,
+        );
+        self.passes.add_quad();
+    }
+
+    fn paint_text(&mut self, mut section: wgpu_glyph::Section<'_>, clip: Rect) {
+        let scale = self.scale;
+        scale_section(&mut section, scale);
+        let physical_clip = Rect::new(
+            Position::new(clip.origin.x * scale, clip.origin.y * scale),
+            Size::new(clip.size.width * scale, clip.size.height * scale),
+        );
+        self.renderer.text.add_text(section, physical_clip);
+    }
+
+    fn push_layer(&mut self, opacity: f32) {
+        let pass = self.passes.push_layer(opacity);
+        self.run(pass);
+        let renderer = &mut *self.renderer;
+        renderer.layer.prepare(
+            &renderer.device,
+            self.passes.layers.len() - 1,
+            renderer.size,
+        );
+    }
+
+    fn pop_layer(&mut self) {
+        let [draw, composite] = self.passes.pop_layer();
+        self.run(draw);
+        self.run(composite);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plans the passes of a display list like `GpuFrame`, without drawing them.
+    #[derive(Default)]
+    struct PlannedPasses {
+        passes: FramePasses,
+        planned: Vec<Pass>,
+    }
+
+    impl PaintBackend for PlannedPasses {
+        fn paint_quad(&mut self, _: Rect, _: [f32; 4]) {
+            self.passes.add_quad();
+        }
+
+        fn paint_text(&mut self, _: wgpu_glyph::Section<'_>, _: Rect) {}
+
+        fn push_layer(&mut self, opacity: f32) {
+            let pass = self.passes.push_layer(opacity);
+            self.planned.push(pass);
+        }
+
+        fn pop_layer(&mut self) {
+            let passes = self.passes.pop_layer();
+            self.planned.extend(passes.iter().cloned());
+        }
+    }
+
+    #[test]
+    fn layers_are_drawn_into_textures_and_composited_beneath() {
+        let rect = Rect::new(Position::zero(), Size::new(10.0, 10.0));
+        let display_list = DisplayList::record(rect.size, |painter| {
+            painter.paint_quad(rect, [1.0; 4]);
+            let mut outer = painter.with_opacity(0.5);
+            outer.paint_quad(rect, [1.0; 4]);
+            outer.with_opacity(0.25).paint_quad(rect, [1.0; 4]);
+            drop(outer);
+            painter.paint_quad(rect, [1.0; 4]);
+        });
+        let mut planned = PlannedPasses::default();
+        display_list.replay(&mut planned);
+        planned.planned.push(planned.passes.draw());
+
+        let draw = |target, clear, quads| Pass::Draw {
+            target,
+            clear,
+            quads,
+        };
+        assert_eq!(
+            planned.planned,
+            vec![
+                draw(PassTarget::Frame, true, 0..1),
+                draw(PassTarget::Layer(0), true, 1..2),
+                draw(PassTarget::Layer(1), true, 2..3),
+                Pass::Composite {
+                    layer: 1,
+                    target: PassTarget::Layer(0),
+                    opacity: 0.25,
+                },
+                draw(PassTarget::Layer(0), false, 3..3),
+                Pass::Composite {
+                    layer: 0,
+                    target: PassTarget::Frame,
+                    opacity: 0.5,
+                },
+                draw(PassTarget::Frame, false, 3..4),
+            ]
+        );
+    }
+}
@@ -0,0 +1,104 @@
+//! Renders widget trees into images without a window or GPU, e.g. for screenshots. Views are
+//! rendered with `HeadlessWindow::render`.
+
+use std::io;
+use std::path::Path;
+
+use crate::geom::Size;
+use crate::renderer::display_list::DisplayList;
+use crate::renderer::software::SoftwareRenderer;
+use crate::view::WidgetTree;
+
+/// An image of sRGB encoded RGBA pixels, in rows from the top left.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Image {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+impl Image {
+    /// Panics if the data isn't 4 bytes for each pixel.
+    pub fn new(width: u32, height: u32, data: Vec<u8>) -> Image {
+        assert_eq!(
+            data.len(),
+            width as usize * height as usize * 4,
+            "Image data doesn't match its size"
+        );
+        Image {
+            width,
+            height,
+            data,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let start = (y as usize * self.width as usize + x as usize) * 4;
+        let mut pixel = [0; 4];
+        pixel.copy_from_slice(&self.data[start..start + 4]);
+        pixel
+    }
+
+    /// Reads an 8 bit RGBA PNG, such as those written by `write_png`.
+    pub fn read_png(reader: impl io::Read) -> io::Result<Image> {
+        let (info, mut reader) = png::Decoder::new(reader).read_info()?;
+        if info.color_type != png::ColorType::RGBA || info.bit_depth != png::BitDepth::Eight {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Only 8 bit RGBA PNGs are supported",
+            ));
+        }
+        let mut data = vec![0; info.buffer_size()];
+        reader.next_frame(&mut data)?;
+        Ok(Image::new(info.width, info.height, data))
+    }
+
+    pub fn load_png(path: impl AsRef<Path>) -> io::Result<Image> {
+        let file = std::fs::File::open(path)?;
+        Image::read_png(io::BufReader::new(file))
+    }
+
+    pub fn write_png(&self, writer: impl io::Write) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.data)?;
+        Ok(())
+    }
+
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut png = Vec::new();
+        self.write_png(&mut png)
+            .expect("Failed to encode image as PNG");
+        png
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let file = std::fs::File::create(path)?;
+        self.write_png(io::BufWriter::new(file))
+    }
+}
+
+/// Paints a tree which has already been laid out on the CPU, into an image of the logical size in
+/// physical pixels.
+pub(crate) fn paint_tree(tree: &WidgetTree, size: Size, scale_factor: f64) -> Image {
+    let width = (size.width as f64 * scale_factor).round() as u32;
+    let height = (size.height as f64 * scale_factor).round() as u32;
+    let display_list = DisplayList::record(size, |painter| tree.paint(None, painter));
+    let mut renderer = SoftwareRenderer::new(width, height);
+    renderer.render(scale_factor, &display_list);
+    Image::new(width, height, renderer.to_rgba8())
+}
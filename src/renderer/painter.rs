@@ -1,73 +1,105 @@
-use crate::geom::{Position, Rect, Size};
-use crate::renderer::Renderer;
+use crate::geom::{Position, Rect};
+use crate::renderer::display_list::DisplayItem;
+use crate::renderer::PaintBackend;
 
+/// Something painted, as given to a backend by `DisplayList::replay`. Collecting these with a
+/// `Vec<PaintCommand>` backend shows where everything ends up. Positions are in logical pixels
+/// relative to the viewport, and quads have already been clipped.
+#[derive(Clone, PartialEq, Debug)]
+pub enum PaintCommand {
+    Quad {
+        rect: Rect,
+        colour: [f32; 4],
+    },
+    Text {
+        /// The text of all the section's parts.
+        text: String,
+        position: Position,
+        clip: Rect,
+    },
+    PushLayer {
+        opacity: f32,
+    },
+    PopLayer,
+}
+
+impl PaintBackend for Vec<PaintCommand> {
+    fn paint_quad(&mut self, rect: Rect, colour: [f32; 4]) {
+        self.push(PaintCommand::Quad { rect, colour });
+    }
+
+    fn paint_text(&mut self, section: wgpu_glyph::Section<'_>, clip: Rect) {
+        self.push(PaintCommand::Text {
+            text: section.text.iter().map(|text| text.text).collect(),
+            position: Position::new(section.screen_position.0, section.screen_position.1),
+            clip,
+        });
+    }
+
+    fn push_layer(&mut self, opacity: f32) {
+        self.push(PaintCommand::PushLayer { opacity });
+    }
+
+    fn pop_layer(&mut self) {
+        self.push(PaintCommand::PopLayer);
+    }
+}
+
+/// Records what widgets paint into a `DisplayList`, in the widget's coordinates.
 pub struct Painter<'a> {
-    renderer: &'a mut Renderer,
-    encoder: &'a mut wgpu::CommandEncoder,
-    viewport_size: Size,
-    scale: f64,
-    origin: Position,
+    items: &'a mut Vec<DisplayItem>,
+    // Ends what this painter started when it's dropped, e.g. a clip
+    pop: Option<DisplayItem>,
 }
 
 impl<'a> Painter<'a> {
-    pub(super) fn new(
-        renderer: &'a mut Renderer,
-        encoder: &'a mut wgpu::CommandEncoder,
-        viewport_size: Size,
-        scale: f64,
-    ) -> Self {
-        renderer.quad.reset();
+    pub(super) fn new(items: &'a mut Vec<DisplayItem>) -> Self {
+        Painter { items, pop: None }
+    }
+
+    fn push(&mut self, push: DisplayItem, pop: DisplayItem) -> Painter<'_> {
+        self.items.push(push);
         Painter {
-            renderer,
-            encoder,
-            viewport_size,
-            scale,
-            origin: Position::zero(),
+            items: &mut *self.items,
+            pop: Some(pop),
         }
     }
 
-    /// Transforms the rect from widget space into Vulkan coordinate space, where the viewport goes
-    /// from -1 to 1.
-    fn transform_rect(&self, mut rect: Rect) -> Rect {
-        rect.origin += self.origin;
-        Rect::new(
-            Position::new(
-                2.0 * rect.origin.x / self.viewport_size.width - 1.0,
-                1.0 - 2.0 * (rect.origin.y + rect.size.height) / self.viewport_size.height,
-            ),
-            Size::new(
-                2.0 * rect.size.width / self.viewport_size.width,
-                2.0 * rect.size.height / self.viewport_size.height,
-            ),
+    pub fn paint_quad(&mut self, rect: Rect, colour: [f32; 4]) {
+        self.items.push(DisplayItem::Quad { rect, colour });
+    }
+
+    pub fn paint_text(&mut self, section: wgpu_glyph::Section<'_>) {
+        self.items.push(DisplayItem::Text(section.to_owned()));
+    }
+
+    /// Returns a painter whose origin is the rect's origin.
+    pub fn with_rect(&mut self, rect: Rect) -> Painter<'_> {
+        self.push(
+            DisplayItem::PushTransform(rect.origin),
+            DisplayItem::PopTransform,
         )
     }
 
-    pub fn paint_quad(&mut self, rect: Rect, colour: [f32; 4]) {
-        let transformed_rect = self.transform_rect(rect);
-        self.renderer.quad.add_quad(
-            &mut self.renderer.belt,
-            &mut self.encoder,
-            &self.renderer.device,
-            transformed_rect,
-            colour,
-        );
+    /// Returns a painter where everything painted outside of the rect isn't shown.
+    pub fn with_clip(&mut self, rect: Rect) -> Painter<'_> {
+        self.push(DisplayItem::PushClip(rect), DisplayItem::PopClip)
     }
 
-    pub fn paint_text(&mut self, mut section: wgpu_glyph::Section<'_>) {
-        section.screen_position = (
-            (section.screen_position.0 + self.origin.x) * self.scale as f32,
-            (section.screen_position.1 + self.origin.y) * self.scale as f32,
-        );
-        self.renderer.text.add_text(section);
+    /// Returns a painter whose painting is faded as a whole by the opacity, from 0 for invisible
+    /// to 1 for opaque.
+    pub fn with_opacity(&mut self, opacity: f32) -> Painter<'_> {
+        self.push(
+            DisplayItem::PushLayer(opacity.clamp(0.0, 1.0)),
+            DisplayItem::PopLayer,
+        )
     }
+}
 
-    pub fn with_rect(&mut self, rect: Rect) -> Painter<'_> {
-        Painter {
-            renderer: self.renderer,
-            encoder: self.encoder,
-            viewport_size: self.viewport_size,
-            scale: self.scale,
-            origin: self.origin + rect.origin,
+impl Drop for Painter<'_> {
+    fn drop(&mut self) {
+        if let Some(pop) = self.pop.take() {
+            self.items.push(pop);
         }
     }
 }
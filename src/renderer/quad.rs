@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use wgpu::util::{DeviceExt, StagingBelt};
 use wgpu::RenderPass;
 
@@ -23,13 +25,27 @@ pub(super) struct QuadPipeline {
     index_buffer: wgpu::Buffer,
     vertex_buffer: wgpu::Buffer,
     instance_buffer: wgpu::Buffer,
+    // How many quads fit in the instance buffer
+    capacity: u64,
     number_of_quads: u32,
 }
 
-macro_rules! shader {
-    ($device:expr, $spv:tt) => {
-        $device.create_shader_module(wgpu::include_spirv!(concat!(env!("OUT_DIR"), "/", $spv)))
-    };
+const INITIAL_CAPACITY: u64 = 128;
+
+/// Blends premultiplied colours over what's already been drawn.
+const PREMULTIPLIED_BLEND: wgpu::BlendDescriptor = wgpu::BlendDescriptor {
+    src_factor: wgpu::BlendFactor::One,
+    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+    operation: wgpu::BlendOperation::Add,
+};
+
+fn create_instance_buffer(device: &wgpu::Device, capacity: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Quad Instance"),
+        size: QUAD_SIZE.get() * capacity,
+        usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+        mapped_at_creation: false,
+    })
 }
 
 impl QuadPipeline {
@@ -65,8 +81,8 @@ impl QuadPipeline {
             }),
             color_states: &[wgpu::ColorStateDescriptor {
                 format,
-                color_blend: wgpu::BlendDescriptor::REPLACE,
-                alpha_blend: wgpu::BlendDescriptor::REPLACE,
+                color_blend: PREMULTIPLIED_BLEND,
+                alpha_blend: PREMULTIPLIED_BLEND,
                 write_mask: wgpu::ColorWrite::ALL,
             }],
             primitive_topology: wgpu::PrimitiveTopology::TriangleStrip,
@@ -105,24 +121,25 @@ impl QuadPipeline {
             usage: wgpu::BufferUsage::VERTEX,
         });
 
-        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Quad Instance"),
-            size: QUAD_SIZE.get() * 128,
-            usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
-            mapped_at_creation: false,
-        });
-
         QuadPipeline {
             pipeline: render_pipeline,
             index_buffer,
             vertex_buffer,
-            instance_buffer,
+            instance_buffer: create_instance_buffer(device, INITIAL_CAPACITY),
+            capacity: INITIAL_CAPACITY,
             number_of_quads: 0,
         }
     }
 
-    pub fn reset(&mut self) {
-        self.number_of_quads = 0
+    /// Starts a frame with up to the given number of quads, growing the instance buffer if it
+    /// can't hold them.
+    pub fn reset(&mut self, device: &wgpu::Device, quads: usize) {
+        self.number_of_quads = 0;
+        let quads = quads as u64;
+        if quads > self.capacity {
+            self.capacity = quads.next_power_of_two();
+            self.instance_buffer = create_instance_buffer(device, self.capacity);
+        }
     }
 
     pub fn add_quad(
@@ -148,11 +165,12 @@ impl QuadPipeline {
         self.number_of_quads += 1;
     }
 
-    pub fn record<'a>(&'a self, encoder: &mut RenderPass<'a>) {
+    /// Draws the quads in the range, by the order they were added.
+    pub fn record<'a>(&'a self, encoder: &mut RenderPass<'a>, quads: Range<u32>) {
         encoder.set_pipeline(&self.pipeline);
         encoder.set_index_buffer(self.index_buffer.slice(..));
         encoder.set_vertex_buffer(0, self.vertex_buffer.slice(..));
         encoder.set_vertex_buffer(1, self.instance_buffer.slice(..));
-        encoder.draw_indexed(0..4, 0, 0..self.number_of_quads)
+        encoder.draw_indexed(0..4, 0, quads)
     }
 }
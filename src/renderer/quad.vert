@@ -8,6 +8,7 @@ layout(location=3) in vec4 q_colour;
 layout(location=0) out vec4 v_colour;
 
 void main() {
-    v_colour = q_colour;
+    // Colours are given with straight alpha, but blended premultiplied
+    v_colour = vec4(q_colour.rgb * q_colour.a, q_colour.a);
     gl_Position = vec4(s_position * q_size + q_position, 0.0, 1.0);
 }
\ No newline at end of file
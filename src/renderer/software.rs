@@ -0,0 +1,221 @@
+//! Paints on the CPU into an image, for machines without a suitable GPU and for rendering
+//! offscreen.
+
+use glyph_brush::{GlyphPositioner, SectionGeometry};
+use wgpu_glyph::ab_glyph::{Font, FontArc};
+
+use crate::geom::{Position, Rect, Size};
+use crate::renderer::display_list::DisplayList;
+use crate::renderer::PaintBackend;
+
+/// The colour the image is cleared to before painting, the same as for windows painted on the GPU.
+const CLEAR_COLOUR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+/// Paints quads and text into an image of the given size in physical pixels.
+pub struct SoftwareRenderer {
+    font: FontArc,
+    width: u32,
+    height: u32,
+    // Each pixel's linear colour with premultiplied alpha, so that blending matches the GPU's
+    pixels: Vec<[f32; 4]>,
+    // The pixels under each layer being painted, with the layer's opacity
+    layers: Vec<(Vec<[f32; 4]>, f32)>,
+    // Of the frame being painted
+    scale: f32,
+}
+
+impl SoftwareRenderer {
+    pub fn new(width: u32, height: u32) -> SoftwareRenderer {
+        SoftwareRenderer {
+            font: super::default_font(),
+            width,
+            height,
+            pixels: vec![CLEAR_COLOUR; (width * height) as usize],
+            layers: Vec::new(),
+            scale: 1.0,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.pixels = vec![CLEAR_COLOUR; (width * height) as usize];
+    }
+
+    /// Clears the image, then paints the display list into it.
+    pub fn render(&mut self, scale_factor: f64, display_list: &DisplayList) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = CLEAR_COLOUR;
+        }
+        self.scale = scale_factor as f32;
+        display_list.replay(self);
+    }
+
+    /// The image as rows of sRGB encoded RGBA pixels, from the top left.
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.pixels.len() * 4);
+        for pixel in self.pixels.iter() {
+            let alpha = pixel[3].clamp(0.0, 1.0);
+            let unpremultiply = if alpha > 0.0 { 1.0 / alpha } else { 0.0 };
+            data.push(linear_to_srgb(pixel[0] * unpremultiply));
+            data.push(linear_to_srgb(pixel[1] * unpremultiply));
+            data.push(linear_to_srgb(pixel[2] * unpremultiply));
+            data.push((alpha * 255.0).round() as u8);
+        }
+        data
+    }
+
+    /// Blends the colour, with straight alpha, over the pixel by how much of the pixel is covered.
+    fn blend(&mut self, x: u32, y: u32, colour: [f32; 4], coverage: f32) {
+        let alpha = colour[3] * coverage;
+        let [r, g, b, _] = colour;
+        let pixel = &mut self.pixels[(y * self.width + x) as usize];
+        *pixel = over([r * alpha, g * alpha, b * alpha, alpha], *pixel);
+    }
+
+    /// Converts the rect into physical pixels, limited to the image.
+    fn physical_rect(&self, rect: Rect) -> Option<Rect> {
+        let scaled = Rect::new(
+            Position::new(rect.origin.x * self.scale, rect.origin.y * self.scale),
+            Size::new(rect.size.width * self.scale, rect.size.height * self.scale),
+        );
+        scaled.intersection(Rect::new(
+            Position::zero(),
+            Size::new(self.width as f32, self.height as f32),
+        ))
+    }
+}
+
+impl PaintBackend for SoftwareRenderer {
+    fn paint_quad(&mut self, rect: Rect, colour: [f32; 4]) {
+        let rect = match self.physical_rect(rect) {
+            Some(rect) => rect,
+            None => return,
+        };
+        let (left, top) = (rect.origin.x, rect.origin.y);
+        let (right, bottom) = (left + rect.size.width, top + rect.size.height);
+        // Pixels along the edges are only partly covered, which antialiases the quad
+        for y in top.floor() as u32..bottom.ceil() as u32 {
+            let y_coverage = bottom.min(y as f32 + 1.0) - top.max(y as f32);
+            for x in left.floor() as u32..right.ceil() as u32 {
+                let x_coverage = right.min(x as f32 + 1.0) - left.max(x as f32);
+                self.blend(x, y, colour, x_coverage * y_coverage);
+            }
+        }
+    }
+
+    fn paint_text(&mut self, mut section: wgpu_glyph::Section<'_>, clip: Rect) {
+        let clip = match self.physical_rect(clip) {
+            Some(clip) => clip,
+            None => return,
+        };
+        super::scale_section(&mut section, self.scale);
+        let geometry = SectionGeometry::from(&section);
+        let font = self.font.clone();
+        let glyphs =
+            section
+                .layout
+                .calculate_glyphs(std::slice::from_ref(&font), &geometry, &section.text);
+        for section_glyph in glyphs {
+            let colour = section.text[section_glyph.section_index].extra.color;
+            let outlined = match font.outline_glyph(section_glyph.glyph) {
+                Some(outlined) => outlined,
+                None => continue,
+            };
+            let bounds = outlined.px_bounds();
+            outlined.draw(|x, y, coverage| {
+                let x = bounds.min.x + x as f32;
+                let y = bounds.min.y + y as f32;
+                if clip.contains(Position::new(x, y)) {
+                    self.blend(x as u32, y as u32, colour, coverage);
+                }
+            });
+        }
+    }
+
+    fn push_layer(&mut self, opacity: f32) {
+        let transparent = vec![[0.0; 4]; self.pixels.len()];
+        let below = std::mem::replace(&mut self.pixels, transparent);
+        self.layers.push((below, opacity));
+    }
+
+    fn pop_layer(&mut self) {
+        let (below, opacity) = self.layers.pop().expect("Unmatched pop_layer");
+        let layer = std::mem::replace(&mut self.pixels, below);
+        for (pixel, layer_pixel) in self.pixels.iter_mut().zip(layer) {
+            let faded = [
+                layer_pixel[0] * opacity,
+                layer_pixel[1] * opacity,
+                layer_pixel[2] * opacity,
+                layer_pixel[3] * opacity,
+            ];
+            *pixel = over(faded, *pixel);
+        }
+    }
+}
+
+/// Composites the premultiplied colours, source over destination.
+fn over(source: [f32; 4], destination: [f32; 4]) -> [f32; 4] {
+    let remaining = 1.0 - source[3];
+    [
+        source[0] + destination[0] * remaining,
+        source[1] + destination[1] * remaining,
+        source[2] + destination[2] * remaining,
+        source[3] + destination[3] * remaining,
+    ]
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let encoded = if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (encoded * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The rows with any of the text's white ink in them.
+    fn text_rows(scale_factor: f64) -> usize {
+        let display_list = DisplayList::record(Size::new(100.0, 50.0), |painter| {
+            painter.paint_text(
+                wgpu_glyph::Section::default()
+                    .with_text(vec![wgpu_glyph::Text::new("H").with_color([1.0; 4])])
+                    .with_screen_position((10.0, 10.0)),
+            )
+        });
+        let size = (100.0 * scale_factor) as u32;
+        let mut renderer = SoftwareRenderer::new(size, size / 2);
+        renderer.render(scale_factor, &display_list);
+        renderer
+            .to_rgba8()
+            .chunks(size as usize * 4)
+            .filter(|row| row.chunks(4).any(|pixel| pixel[0] > 128))
+            .count()
+    }
+
+    #[test]
+    fn text_is_scaled_with_the_scale_factor() {
+        let logical = text_rows(1.0);
+        assert!(logical > 0);
+        let physical = text_rows(2.0);
+        assert!(
+            (physical as i32 - logical as i32 * 2).abs() <= 1,
+            "{} rows at scale 1, {} at scale 2",
+            logical,
+            physical
+        );
+    }
+}
@@ -1,24 +1,29 @@
+use glyph_brush::OwnedSection;
 use wgpu::util::StagingBelt;
-use wgpu::{CommandEncoder, SwapChainTexture};
-use wgpu_glyph::GlyphBrush;
+use wgpu::{CommandEncoder, TextureView};
+use wgpu_glyph::{GlyphBrush, Region};
+
+use crate::geom::Rect;
 
 pub(super) struct TextPipeline {
     glyph_brush: GlyphBrush<()>,
+    // Each section with the rect it's clipped to, in physical pixels
+    sections: Vec<(Rect, OwnedSection)>,
 }
 
 impl TextPipeline {
     pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> TextPipeline {
-        let font =
-            wgpu_glyph::ab_glyph::FontArc::try_from_slice(include_bytes!("Roboto-Regular.ttf"))
-                .unwrap();
-
-        let glyph_brush = wgpu_glyph::GlyphBrushBuilder::using_font(font).build(device, format);
+        let glyph_brush =
+            wgpu_glyph::GlyphBrushBuilder::using_font(super::default_font()).build(device, format);
 
-        TextPipeline { glyph_brush }
+        TextPipeline {
+            glyph_brush,
+            sections: Vec::new(),
+        }
     }
 
-    pub fn add_text(&mut self, section: wgpu_glyph::Section) {
-        self.glyph_brush.queue(section);
+    pub fn add_text(&mut self, section: wgpu_glyph::Section, clip: Rect) {
+        self.sections.push((clip, section.to_owned()));
     }
 
     pub fn record(
@@ -26,18 +31,53 @@ impl TextPipeline {
         device: &wgpu::Device,
         staging_belt: &mut StagingBelt,
         encoder: &mut CommandEncoder,
-        frame: &SwapChainTexture,
+        target: &TextureView,
         size: winit::dpi::PhysicalSize<u32>,
     ) {
-        self.glyph_brush
-            .draw_queued(
-                device,
-                staging_belt,
-                encoder,
-                &frame.view,
-                size.width,
-                size.height,
-            )
-            .expect("Draw queued");
+        let transform = wgpu_glyph::orthographic_projection(size.width, size.height);
+        let mut sections = self.sections.drain(..).peekable();
+        // Sections with the same clip are drawn together, as scissoring applies to a whole draw
+        while let Some((clip, section)) = sections.next() {
+            let mut group = vec![section];
+            while let Some((_, section)) = sections.next_if(|(next_clip, _)| *next_clip == clip) {
+                group.push(section);
+            }
+
+            let region = match clip_region(clip, size) {
+                Some(region) => region,
+                None => continue,
+            };
+            for section in group.iter() {
+                self.glyph_brush.queue(section);
+            }
+            self.glyph_brush
+                .draw_queued_with_transform_and_scissoring(
+                    device,
+                    staging_belt,
+                    encoder,
+                    target,
+                    transform,
+                    region,
+                )
+                .expect("Draw queued");
+        }
+    }
+}
+
+/// Converts the clip into a scissor region within the frame, None if it's empty.
+fn clip_region(clip: Rect, size: winit::dpi::PhysicalSize<u32>) -> Option<Region> {
+    let x = clip.origin.x.max(0.0).floor() as u32;
+    let y = clip.origin.y.max(0.0).floor() as u32;
+    let right = ((clip.origin.x + clip.size.width).ceil() as u32).min(size.width);
+    let bottom = ((clip.origin.y + clip.size.height).ceil() as u32).min(size.height);
+    if x < right && y < bottom {
+        Some(Region {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        })
+    } else {
+        None
     }
 }
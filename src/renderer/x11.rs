@@ -0,0 +1,89 @@
+//! Shows images painted on the CPU in X11 windows.
+
+use std::os::raw::{c_char, c_int, c_uint};
+
+use winit::platform::unix::WindowExtUnix;
+use x11_dl::xlib;
+
+use crate::renderer::software::SoftwareRenderer;
+
+pub(crate) struct XlibSurface {
+    // Boxed as it holds every Xlib function
+    xlib: Box<xlib::Xlib>,
+    display: *mut xlib::Display,
+    window: xlib::Window,
+    gc: xlib::GC,
+    // The image converted to the window's pixel format
+    pixels: Vec<u32>,
+}
+
+impl XlibSurface {
+    /// None if the window isn't an X11 window or Xlib can't be loaded.
+    pub fn new(window: &winit::window::Window) -> Option<XlibSurface> {
+        let xlib = Box::new(xlib::Xlib::open().ok()?);
+        let display = window.xlib_display()? as *mut xlib::Display;
+        let window = window.xlib_window()?;
+        let gc = unsafe { (xlib.XCreateGC)(display, window, 0, std::ptr::null_mut()) };
+        Some(XlibSurface {
+            xlib,
+            display,
+            window,
+            gc,
+            pixels: Vec::new(),
+        })
+    }
+
+    /// Copies the renderer's image into the window. Expects the window to use the default 24 bit
+    /// true colour visual.
+    pub fn present(&mut self, renderer: &SoftwareRenderer) {
+        let (width, height) = (renderer.width(), renderer.height());
+        self.pixels.clear();
+        self.pixels.extend(
+            renderer
+                .to_rgba8()
+                .chunks_exact(4)
+                .map(|rgba| u32::from_be_bytes([0, rgba[0], rgba[1], rgba[2]])),
+        );
+        unsafe {
+            let screen = (self.xlib.XDefaultScreen)(self.display);
+            let mut image: xlib::XImage = std::mem::zeroed();
+            image.width = width as c_int;
+            image.height = height as c_int;
+            image.format = xlib::ZPixmap;
+            image.data = self.pixels.as_mut_ptr() as *mut c_char;
+            image.byte_order = xlib::LSBFirst;
+            image.bitmap_unit = 32;
+            image.bitmap_bit_order = xlib::LSBFirst;
+            image.bitmap_pad = 32;
+            image.depth = (self.xlib.XDefaultDepth)(self.display, screen);
+            image.bytes_per_line = width as c_int * 4;
+            image.bits_per_pixel = 32;
+            image.red_mask = 0xff0000;
+            image.green_mask = 0x00ff00;
+            image.blue_mask = 0x0000ff;
+            // The image and its data are owned here, so it's never destroyed by Xlib
+            (self.xlib.XInitImage)(&mut image);
+            (self.xlib.XPutImage)(
+                self.display,
+                self.window,
+                self.gc,
+                &mut image,
+                0,
+                0,
+                0,
+                0,
+                width as c_uint,
+                height as c_uint,
+            );
+            (self.xlib.XFlush)(self.display);
+        }
+    }
+}
+
+impl Drop for XlibSurface {
+    fn drop(&mut self) {
+        unsafe {
+            (self.xlib.XFreeGC)(self.display, self.gc);
+        }
+    }
+}
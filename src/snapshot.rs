@@ -0,0 +1,259 @@
+//! Compares rendered images with reference images, so that changes to how widgets look are caught
+//! by tests.
+
+use std::path::{Path, PathBuf};
+
+use crate::renderer::offscreen::Image;
+
+/// When set, `assert_snapshot` saves the images as the new references instead of comparing them.
+pub const BLESS_VAR: &str = "KAKAPO_BLESS";
+
+/// Colour of the pixels which differ in diff images.
+const DIFF_COLOUR: [u8; 4] = [255, 0, 0, 255];
+
+/// The result of comparing an image with a reference image.
+pub struct Comparison {
+    /// How many pixels differ by more than the tolerance, or None if the sizes differ.
+    pub differing_pixels: Option<usize>,
+    /// The reference faded, with the differing pixels highlighted. None if the sizes differ.
+    pub diff: Option<Image>,
+}
+
+impl Comparison {
+    pub fn matches(&self) -> bool {
+        self.differing_pixels == Some(0)
+    }
+}
+
+/// Compares the images, pixels match if each of their channels differs by at most the tolerance.
+pub fn compare(reference: &Image, actual: &Image, tolerance: u8) -> Comparison {
+    if reference.width() != actual.width() || reference.height() != actual.height() {
+        return Comparison {
+            differing_pixels: None,
+            diff: None,
+        };
+    }
+    let mut differing_pixels = 0;
+    let mut diff = Vec::with_capacity(reference.data().len());
+    for (expected, pixel) in reference
+        .data()
+        .chunks_exact(4)
+        .zip(actual.data().chunks_exact(4))
+    {
+        let differs = expected
+            .iter()
+            .zip(pixel)
+            .any(|(&a, &b)| (a as i16 - b as i16).abs() > tolerance as i16);
+        if differs {
+            differing_pixels += 1;
+            diff.extend_from_slice(&DIFF_COLOUR);
+        } else {
+            let grey = ((expected[0] as u16 + expected[1] as u16 + expected[2] as u16) / 12) as u8;
+            diff.extend_from_slice(&[grey, grey, grey, 255]);
+        }
+    }
+    Comparison {
+        differing_pixels: Some(differing_pixels),
+        diff: Some(Image::new(reference.width(), reference.height(), diff)),
+    }
+}
+
+/// Asserts that the image matches the reference PNG at the path, within the per channel
+/// tolerance. When it doesn't, the image is written next to the reference as `<name>.actual.png`
+/// along with `<name>.diff.png` showing where they differ, and then this panics.
+///
+/// Setting the `KAKAPO_BLESS` environment variable saves the image as the reference instead, which
+/// is also how references are first created.
+pub fn assert_snapshot(image: &Image, path: impl AsRef<Path>, tolerance: u8) {
+    let bless = std::env::var_os(BLESS_VAR).is_some();
+    check_snapshot(image, path.as_ref(), tolerance, bless);
+}
+
+/// Does what `assert_snapshot` does, with whether to bless passed in rather than read from the
+/// environment.
+fn check_snapshot(image: &Image, path: &Path, tolerance: u8, bless: bool) {
+    if bless {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).expect("Failed to create snapshot directory");
+        }
+        image.save_png(path).expect("Failed to save snapshot");
+        remove_failure(path);
+        return;
+    }
+    let reference = match Image::load_png(path) {
+        Ok(reference) => reference,
+        Err(err) => {
+            save_failure(image, None, path);
+            panic!(
+                "Failed to load snapshot {}: {}, set {} to create it",
+                path.display(),
+                err,
+                BLESS_VAR
+            );
+        }
+    };
+    let comparison = compare(&reference, image, tolerance);
+    if comparison.matches() {
+        remove_failure(path);
+        return;
+    }
+    save_failure(image, comparison.diff.as_ref(), path);
+    match comparison.differing_pixels {
+        Some(count) => panic!(
+            "{} pixels differ from snapshot {}, see {}",
+            count,
+            path.display(),
+            sibling(path, "diff").display()
+        ),
+        None => panic!(
+            "Image is {}x{} but snapshot {} is {}x{}",
+            image.width(),
+            image.height(),
+            path.display(),
+            reference.width(),
+            reference.height()
+        ),
+    }
+}
+
+/// Saves the images for inspecting a failed comparison, ignoring errors so that the failure
+/// itself is reported.
+fn save_failure(image: &Image, diff: Option<&Image>, path: &Path) {
+    let _ = image.save_png(sibling(path, "actual"));
+    if let Some(diff) = diff {
+        let _ = diff.save_png(sibling(path, "diff"));
+    }
+}
+
+/// Removes the images from a previous failure, if any.
+fn remove_failure(path: &Path) {
+    let _ = std::fs::remove_file(sibling(path, "actual"));
+    let _ = std::fs::remove_file(sibling(path, "diff"));
+}
+
+/// The path of another image for the same snapshot, e.g. `button.diff.png` for `button.png`.
+fn sibling(path: &Path, kind: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.{}.png", stem, kind))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+
+    use super::*;
+
+    fn solid(width: u32, height: u32, pixel: [u8; 4]) -> Image {
+        Image::new(width, height, pixel.repeat((width * height) as usize))
+    }
+
+    fn with_pixel(image: &Image, x: u32, y: u32, pixel: [u8; 4]) -> Image {
+        let mut data = image.data().to_vec();
+        let start = ((y * image.width() + x) * 4) as usize;
+        data[start..start + 4].copy_from_slice(&pixel);
+        Image::new(image.width(), image.height(), data)
+    }
+
+    /// An empty directory for the test's snapshots.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kakapo-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn compare_matches_within_tolerance() {
+        let reference = solid(3, 2, [100, 100, 100, 255]);
+        let actual = with_pixel(&reference, 1, 1, [102, 98, 100, 255]);
+        assert!(compare(&reference, &reference, 0).matches());
+        assert!(compare(&reference, &actual, 2).matches());
+        assert!(!compare(&reference, &actual, 1).matches());
+    }
+
+    #[test]
+    fn compare_counts_and_highlights_differing_pixels() {
+        let reference = solid(3, 2, [60, 60, 60, 255]);
+        let actual = with_pixel(&reference, 2, 0, [60, 60, 60, 0]);
+        let actual = with_pixel(&actual, 0, 1, [255, 60, 60, 255]);
+        let comparison = compare(&reference, &actual, 10);
+        assert_eq!(comparison.differing_pixels, Some(2));
+        let diff = comparison.diff.unwrap();
+        assert_eq!(diff.pixel(2, 0), DIFF_COLOUR);
+        assert_eq!(diff.pixel(0, 1), DIFF_COLOUR);
+        assert_eq!(diff.pixel(0, 0), [15, 15, 15, 255]);
+    }
+
+    #[test]
+    fn compare_rejects_different_sizes() {
+        let comparison = compare(&solid(2, 3, [0; 4]), &solid(3, 2, [0; 4]), 255);
+        assert!(!comparison.matches());
+        assert_eq!(comparison.differing_pixels, None);
+        assert!(comparison.diff.is_none());
+    }
+
+    #[test]
+    fn bless_saves_reference_and_removes_failures() {
+        let dir = temp_dir("bless");
+        let path = dir.join("nested").join("button.png");
+        let image = solid(2, 2, [10, 20, 30, 255]);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        image.save_png(sibling(&path, "actual")).unwrap();
+        image.save_png(sibling(&path, "diff")).unwrap();
+
+        check_snapshot(&image, &path, 0, true);
+
+        assert_eq!(Image::load_png(&path).unwrap(), image);
+        assert!(!sibling(&path, "actual").exists());
+        assert!(!sibling(&path, "diff").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn mismatch_saves_failure_until_matching() {
+        let dir = temp_dir("mismatch");
+        let path = dir.join("button.png");
+        let reference = solid(2, 2, [10, 20, 30, 255]);
+        reference.save_png(&path).unwrap();
+        let actual = with_pixel(&reference, 0, 0, [200, 20, 30, 255]);
+
+        let result = panic::catch_unwind(|| check_snapshot(&actual, &path, 0, false));
+        assert!(result.is_err());
+        assert_eq!(Image::load_png(sibling(&path, "actual")).unwrap(), actual);
+        let diff = Image::load_png(sibling(&path, "diff")).unwrap();
+        assert_eq!(diff.pixel(0, 0), DIFF_COLOUR);
+
+        check_snapshot(&reference, &path, 0, false);
+        assert!(!sibling(&path, "actual").exists());
+        assert!(!sibling(&path, "diff").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_reference_saves_actual() {
+        let dir = temp_dir("missing");
+        let path = dir.join("button.png");
+        let image = solid(1, 1, [0, 0, 0, 255]);
+
+        let result = panic::catch_unwind(|| check_snapshot(&image, &path, 0, false));
+        assert!(result.is_err());
+        assert!(!path.exists());
+        assert_eq!(Image::load_png(sibling(&path, "actual")).unwrap(), image);
+        assert!(!sibling(&path, "diff").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn size_mismatch_saves_actual_without_diff() {
+        let dir = temp_dir("size");
+        let path = dir.join("button.png");
+        solid(2, 2, [0, 0, 0, 255]).save_png(&path).unwrap();
+        let image = solid(3, 2, [0, 0, 0, 255]);
+
+        let result = panic::catch_unwind(|| check_snapshot(&image, &path, 255, false));
+        assert!(result.is_err());
+        assert_eq!(Image::load_png(sibling(&path, "actual")).unwrap(), image);
+        assert!(!sibling(&path, "diff").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
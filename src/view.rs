@@ -1,33 +1,48 @@
 use std::any::Any;
+use std::cell::{Cell, RefCell};
 use std::collections::{HashMap, HashSet};
-use std::sync::{Arc, Mutex};
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex, Weak};
 
 use crate::app::AppInner;
 use crate::description::Description;
-use crate::events::Event;
-use crate::geom::{Position, Rect, Size};
+use crate::events::{Event, EventResult, Focus, MouseEvent, ScrollEvent};
+use crate::geom::{Constraints, Position, Rect, Size, SizeHint};
 use crate::renderer::painter::Painter;
 use crate::view_model::ViewModel;
 
 #[derive(Copy, Clone, Eq, PartialEq, Hash)]
 pub(crate) struct ViewId(pub(crate) u64);
 
-struct ViewData<V: View + ?Sized> {
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub(crate) struct WidgetId(pub(crate) u64);
+
+struct ViewData<V: DynView + ?Sized> {
     view_id: ViewId,
+    // Keeps the view registered with its view model's ViewRefs for as long as the view exists
+    _registration: Arc<ViewRegistration>,
+    // Registered with the observables read by the last build of the view, replaced on each build
+    dependencies: Option<Arc<ViewRegistration>>,
     widget: Option<WidgetTree>,
-    user_data: Option<Box<dyn Any>>,
+    cache: LayoutCache,
     view: V,
 }
 
-pub type UserData<'a> = Option<&'a dyn Any>;
-pub type UserDataMut<'a> = Option<&'a mut dyn Any>;
+/// The model of the view enclosing a subtree, None above the root view.
+type UserData<'a> = Option<&'a dyn Any>;
+type UserDataMut<'a> = Option<&'a mut dyn Any>;
+
+/// Widgets are only created by the factories of their view's model.
+const MODEL_MISMATCH: &str = "Widget isn't within a view of its model";
 
-#[derive(Eq, PartialEq, Hash, Copy, Clone)]
+#[derive(Eq, PartialEq, Hash, Clone)]
 enum WidgetKeyInner {
     Location(&'static std::panic::Location<'static>),
+    Value(&'static std::panic::Location<'static>, KeyValue),
 }
 
-#[derive(Eq, PartialEq, Hash, Copy, Clone)]
+/// Identifies a widget between rebuilds of its view, so that the widget keeps its state.
+#[derive(Eq, PartialEq, Hash, Clone)]
 pub struct WidgetKey {
     inner: WidgetKeyInner,
 }
@@ -39,14 +54,95 @@ impl WidgetKey {
             inner: WidgetKeyInner::Location(std::panic::Location::caller()),
         }
     }
+
+    /// A key for one of several widgets created at the caller's location, e.g. within a loop.
+    #[track_caller]
+    pub fn caller_with(value: impl Into<KeyValue>) -> WidgetKey {
+        WidgetKey::caller().with_value(value)
+    }
+
+    /// Distinguishes this key from others created at the same location, the value should be
+    /// unique among them and stay the same for the same item when a list is reordered.
+    pub fn with_value(self, value: impl Into<KeyValue>) -> WidgetKey {
+        let location = match self.inner {
+            WidgetKeyInner::Location(location) | WidgetKeyInner::Value(location, _) => location,
+        };
+        WidgetKey {
+            inner: WidgetKeyInner::Value(location, value.into()),
+        }
+    }
+
+    /// The value given by `with_value`, if any.
+    pub fn value(&self) -> Option<&KeyValue> {
+        match self.inner {
+            WidgetKeyInner::Location(_) => None,
+            WidgetKeyInner::Value(_, ref value) => Some(value),
+        }
+    }
 }
 
-pub struct WidgetState<'a> {
+/// A user provided value within a `WidgetKey`, such as an id, an index or a name.
+#[derive(Eq, PartialEq, Hash, Clone, Debug)]
+pub enum KeyValue {
+    Int(u64),
+    /// Negative integers, other signed integers are `Int` so that they equal the same unsigned
+    /// integer.
+    NegativeInt(i64),
+    Str(Arc<str>),
+}
+
+impl From<u64> for KeyValue {
+    fn from(value: u64) -> KeyValue {
+        KeyValue::Int(value)
+    }
+}
+
+impl From<u32> for KeyValue {
+    fn from(value: u32) -> KeyValue {
+        KeyValue::Int(value as u64)
+    }
+}
+
+impl From<usize> for KeyValue {
+    fn from(value: usize) -> KeyValue {
+        KeyValue::Int(value as u64)
+    }
+}
+
+impl From<i64> for KeyValue {
+    fn from(value: i64) -> KeyValue {
+        if value < 0 {
+            KeyValue::NegativeInt(value)
+        } else {
+            KeyValue::Int(value as u64)
+        }
+    }
+}
+
+impl From<i32> for KeyValue {
+    fn from(value: i32) -> KeyValue {
+        KeyValue::from(value as i64)
+    }
+}
+
+impl From<&str> for KeyValue {
+    fn from(value: &str) -> KeyValue {
+        KeyValue::Str(value.into())
+    }
+}
+
+impl From<String> for KeyValue {
+    fn from(value: String) -> KeyValue {
+        KeyValue::Str(value.into())
+    }
+}
+
+pub struct WidgetState<'a, M: ?Sized> {
     rect: Rect,
-    user_data: UserData<'a>,
+    model: &'a M,
 }
 
-impl<'a> WidgetState<'a> {
+impl<'a, M: ?Sized> WidgetState<'a, M> {
     pub fn rect(&self) -> Rect {
         self.rect
     }
@@ -56,20 +152,31 @@ impl<'a> WidgetState<'a> {
         Rect::new(Position::zero(), rect.size)
     }
 
-    pub fn user_data(&self) -> UserData<'_> {
-        match self.user_data {
-            Some(ref data) => Some(*data),
-            None => None,
+    /// The model of the view the widget is in.
+    pub fn model(&self) -> &M {
+        self.model
+    }
+}
+
+impl<'a> WidgetState<'a, dyn Any> {
+    fn downcast<M: 'static>(self) -> WidgetState<'a, M> {
+        WidgetState {
+            rect: self.rect,
+            model: self.model.downcast_ref().expect(MODEL_MISMATCH),
         }
     }
 }
 
-pub struct WidgetStateMut<'a> {
+pub struct WidgetStateMut<'a, M: ?Sized> {
+    id: WidgetId,
     rect: Rect,
-    user_data: UserDataMut<'a>,
+    model: &'a mut M,
+    focus: &'a mut Focus,
+    relayout: &'a mut bool,
+    repaint: &'a mut bool,
 }
 
-impl<'a> WidgetStateMut<'a> {
+impl<'a, M: ?Sized> WidgetStateMut<'a, M> {
     pub fn rect(&self) -> Rect {
         self.rect
     }
@@ -79,82 +186,311 @@ impl<'a> WidgetStateMut<'a> {
         Rect::new(Position::zero(), rect.size)
     }
 
-    pub fn user_data(&mut self) -> UserDataMut<'_> {
-        match self.user_data {
-            Some(ref mut data) => Some(*data),
-            None => None,
+    /// See `WidgetState::model`.
+    pub fn model(&self) -> &M {
+        self.model
+    }
+
+    pub fn model_mut(&mut self) -> &mut M {
+        self.model
+    }
+
+    /// Whether this widget receives keyboard events once the current event has been processed.
+    pub fn has_focus(&self) -> bool {
+        self.focus.has_focus(self.id)
+    }
+
+    /// Makes this widget the window's focus owner, taking focus from the previous owner. Ignored
+    /// while handling `FocusIn` or `FocusOut`.
+    pub fn request_focus(&mut self) {
+        self.focus.request(self.id);
+    }
+
+    /// Gives up focus if this widget has it, afterwards no widget in the window has focus.
+    pub fn release_focus(&mut self) {
+        self.focus.release(self.id);
+    }
+
+    /// Lays out the widget's children again once the event has been handled, for when the widget
+    /// moves its children around, e.g. by scrolling.
+    pub fn request_layout(&mut self) {
+        *self.relayout = true;
+    }
+
+    /// Paints the widget again once the event has been handled, for when the event changes how the
+    /// widget looks.
+    pub fn request_paint(&mut self) {
+        *self.repaint = true;
+    }
+}
+
+impl<'a> WidgetStateMut<'a, dyn Any> {
+    fn downcast<M: 'static>(self) -> WidgetStateMut<'a, M> {
+        WidgetStateMut {
+            id: self.id,
+            rect: self.rect,
+            model: self.model.downcast_mut().expect(MODEL_MISMATCH),
+            focus: self.focus,
+            relayout: self.relayout,
+            repaint: self.repaint,
         }
     }
 }
 
-struct WidgetData<W: Widget + ?Sized> {
+/// The state of a window which changes as its widgets handle events.
+#[derive(Default)]
+pub(crate) struct WindowState {
+    pub(crate) focus: Focus,
+    pub(crate) damage: Damage,
+}
+
+/// The area of a window, in logical coordinates, which needs painting again.
+#[derive(Default)]
+pub(crate) struct Damage {
+    rect: Option<Rect>,
+}
+
+impl Damage {
+    pub(crate) fn add(&mut self, rect: Rect) {
+        self.rect = Some(match self.rect {
+            Some(damaged) => damaged.union(rect),
+            None => rect,
+        });
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.rect.is_none()
+    }
+
+    pub(crate) fn take(&mut self) -> Option<Rect> {
+        self.rect.take()
+    }
+}
+
+struct WidgetData<W: DynWidget + ?Sized> {
+    id: WidgetId,
     key: WidgetKey,
     allocation: Option<Rect>,
+    cache: LayoutCache,
     children: Vec<WidgetTree>,
     widget: W,
 }
 
+/// The results of the last layout of a subtree, so that unchanged subtrees aren't measured and laid
+/// out again.
+#[derive(Default)]
+struct LayoutCache {
+    /// The context the subtree was last laid out with.
+    ctx: Option<LayoutContext>,
+    size_hint: Cell<Option<SizeHint>>,
+    /// The children when the widget or layout was deconstructed, see `WidgetTree::identity`.
+    children: Vec<*const ()>,
+}
+
+impl LayoutCache {
+    fn size_hint(&self, measure: impl FnOnce() -> SizeHint) -> SizeHint {
+        if let Some(size_hint) = self.size_hint.get() {
+            return size_hint;
+        }
+        let size_hint = measure();
+        self.size_hint.set(Some(size_hint));
+        size_hint
+    }
+
+    /// Forgets the last layout, returning the previous size hint and layout context.
+    fn take(&mut self) -> (Option<SizeHint>, Option<LayoutContext>) {
+        (self.size_hint.take(), self.ctx.take())
+    }
+
+    /// Whether the last layout still holds after a description was applied, which is when the
+    /// children are the same laid out subtrees as before and the size hint hasn't changed.
+    fn is_valid(&self, children: &[WidgetTree], measure: impl FnOnce() -> SizeHint) -> bool {
+        let same_children = self.children.len() == children.len()
+            && self
+                .children
+                .iter()
+                .zip(children)
+                .all(|(&previous, child)| previous == child.identity() && child.is_laid_out());
+        same_children && self.size_hint.get() == Some(measure())
+    }
+}
+
+/// Where the event is within the dispatch of the event to its target, see `WidgetTree::dispatch`.
+#[derive(Copy, Clone)]
+enum Phase {
+    Capture,
+    Bubble,
+}
+
+/// What an event is sent to.
+#[derive(Copy, Clone)]
+enum Target {
+    /// The deepest widget at the position.
+    Position(Position),
+    Widget(WidgetId),
+}
+
+impl WidgetData<dyn DynWidget> {
+    fn handle(
+        &mut self,
+        phase: Phase,
+        user_data: UserDataMut<'_>,
+        event: Event,
+        window: &mut WindowState,
+    ) -> EventResult {
+        let rect = self.allocation.unwrap();
+        let mut relayout = false;
+        let mut repaint = false;
+        let state = WidgetStateMut {
+            id: self.id,
+            rect,
+            model: user_data.expect("Widget isn't within a view"),
+            focus: &mut window.focus,
+            relayout: &mut relayout,
+            repaint: &mut repaint,
+        };
+        let event = event.relative_to(rect.origin);
+        let result = self.widget.event(phase, state, event);
+        if relayout {
+            // The widget keeps its rect, only its children move
+            let ctx = LayoutContext::new(rect.origin, Constraints::tight(rect.size));
+            self.widget.layout(self.children.as_mut_slice(), ctx);
+        }
+        if relayout || repaint {
+            window.damage.add(rect);
+        }
+        result
+    }
+
+    /// Paints the widget, then its children clipped to the widget, then over its children.
+    fn paint(&self, model: &dyn Any, painter: &mut Painter<'_>) {
+        let rect = self.allocation.unwrap();
+        let state = WidgetState { rect, model };
+        self.widget.paint(state, &mut painter.with_rect(rect));
+        if !self.children.is_empty() {
+            {
+                let mut clipped = painter.with_clip(rect);
+                for child in self.children.iter() {
+                    child.paint(Some(model), &mut clipped);
+                }
+            }
+            let state = WidgetState { rect, model };
+            self.widget
+                .paint_over_children(state, &mut painter.with_rect(rect));
+        }
+    }
+}
+
+impl ViewData<dyn DynView> {
+    /// Builds the view's widgets, registering the view with the observables it reads.
+    fn build(
+        &mut self,
+        app: &Arc<AppInner>,
+        cached: HashMap<WidgetKey, Vec<WidgetTree>>,
+    ) -> WidgetTree {
+        let dependencies = Arc::new(ViewRegistration {
+            app: Arc::clone(app),
+            view_id: self.view_id,
+        });
+        let previous =
+            BUILDING_VIEW.with(|building| building.replace(Some(Arc::clone(&dependencies))));
+        let tree = self.view.view(app, cached);
+        BUILDING_VIEW.with(|building| building.replace(previous));
+        // Dropping the previous dependencies unregisters the view from observables it no longer
+        // reads
+        self.dependencies = Some(dependencies);
+        tree
+    }
+}
+
 struct LayoutData<L: Layout + ?Sized> {
+    key: WidgetKey,
     allocation: Option<Rect>,
+    cache: LayoutCache,
     children: Vec<WidgetTree>,
     layout: L,
 }
 
 enum WidgetTreeInner {
-    View(Box<ViewData<dyn View>>),
-    Widget(Box<WidgetData<dyn Widget>>),
+    View(Box<ViewData<dyn DynView>>),
+    Widget(Box<WidgetData<dyn DynWidget>>),
     Layout(Box<LayoutData<dyn Layout>>),
 }
 
-pub struct WidgetTreeFactory {
-    pub(crate) app: Arc<AppInner>,
+/// Creates the widgets, layouts and views within a view whose model is `M`.
+pub struct WidgetTreeFactory<M> {
+    app: Arc<AppInner>,
+    model: PhantomData<fn(&mut M)>,
 }
 
-impl WidgetTreeFactory {
-    fn new(&self, inner: WidgetTreeInner) -> WidgetTree {
+impl<M> WidgetTreeFactory<M> {
+    pub(crate) fn new(app: Arc<AppInner>) -> WidgetTreeFactory<M> {
+        WidgetTreeFactory {
+            app,
+            model: PhantomData,
+        }
+    }
+
+    fn tree(&self, inner: WidgetTreeInner) -> WidgetTree {
         WidgetTree {
             app: Arc::clone(&self.app),
             inner,
         }
     }
 
-    pub fn new_view<V: View + 'static, D: ViewModel + 'static>(
-        &self,
-        view: V,
-        user_data: D,
-    ) -> WidgetTree {
+    pub fn new_view<V: View + 'static>(&self, view: V, model: V::Model) -> WidgetTree {
         let view_id = self.app.new_view_id();
-        user_data.view_refs().init(Arc::clone(&self.app), view_id);
+        let registration = model.view_refs().register(Arc::clone(&self.app), view_id);
 
-        self.new(WidgetTreeInner::View(Box::new(ViewData {
+        self.tree(WidgetTreeInner::View(Box::new(ViewData {
             view_id,
+            _registration: registration,
+            dependencies: None,
             widget: None,
-            user_data: Some(Box::new(user_data) as Box<dyn Any>),
-            view,
-        }) as Box<ViewData<dyn View>>))
+            cache: LayoutCache::default(),
+            view: ViewWithModel { view, model },
+        })
+            as Box<ViewData<dyn DynView>>))
     }
 
-    pub fn new_widget<W: Widget + 'static>(&self, key: WidgetKey, widget: W) -> WidgetTree {
-        self.new(WidgetTreeInner::Widget(Box::new(WidgetData {
+    /// Creates a layout, whose children are given by its description's `children`.
+    pub fn new_layout<L: Layout + 'static>(&self, key: WidgetKey, layout: L) -> WidgetTree {
+        self.tree(WidgetTreeInner::Layout(Box::new(LayoutData {
             key,
             allocation: None,
+            cache: LayoutCache::default(),
             children: Vec::new(),
-            widget,
+            layout,
         })
-            as Box<WidgetData<dyn Widget>>))
+            as Box<LayoutData<dyn Layout>>))
     }
+}
 
-    pub fn new_layout<L: Layout + 'static>(
+impl<M: 'static> WidgetTreeFactory<M> {
+    pub fn new_widget<W: Widget<M> + 'static>(&self, key: WidgetKey, widget: W) -> WidgetTree {
+        self.new_widget_with_id(key, |_, _| widget)
+    }
+
+    /// Creates a widget which needs to know its own id, e.g. to ask for updates.
+    pub(crate) fn new_widget_with_id<W: Widget<M> + 'static>(
         &self,
-        layout: L,
-        children: Vec<WidgetTree>,
+        key: WidgetKey,
+        widget: impl FnOnce(&Arc<AppInner>, WidgetId) -> W,
     ) -> WidgetTree {
-        self.new(WidgetTreeInner::Layout(Box::new(LayoutData {
+        let id = self.app.new_widget_id();
+        let widget = WidgetWithModel {
+            widget: widget(&self.app, id),
+            model: PhantomData,
+        };
+        self.tree(WidgetTreeInner::Widget(Box::new(WidgetData {
+            id,
+            key,
             allocation: None,
-            children,
-            layout,
+            cache: LayoutCache::default(),
+            children: Vec::new(),
+            widget,
         })
-            as Box<LayoutData<dyn Layout>>))
+            as Box<WidgetData<dyn DynWidget>>))
     }
 }
 
@@ -164,142 +500,363 @@ pub struct WidgetTree {
 }
 
 impl WidgetTree {
-    pub(crate) fn materialise_views(&mut self, user_data: UserData<'_>) {
+    pub(crate) fn materialise_views(&mut self) {
         match self.inner {
             WidgetTreeInner::View(ref mut view) => {
                 if view.widget.is_none() {
-                    let user_data = view.user_data.as_deref().or(user_data);
-                    let mut cache = WidgetCache {
-                        factory: WidgetTreeFactory {
-                            app: Arc::clone(&self.app),
-                        },
-                        cached: HashMap::new(),
-                    };
-                    let mut tree = view.view.view(&mut cache, user_data);
-                    tree.materialise_views(user_data);
+                    let mut tree = view.build(&self.app, HashMap::new());
+                    tree.materialise_views();
                     view.widget = Some(tree);
                 }
             }
             WidgetTreeInner::Widget(ref mut w) => {
                 for child in w.children.iter_mut() {
-                    child.materialise_views(user_data);
+                    child.materialise_views();
                 }
             }
             WidgetTreeInner::Layout(ref mut layout) => {
                 for child in layout.children.iter_mut() {
-                    child.materialise_views(user_data);
+                    child.materialise_views();
                 }
             }
         }
     }
 
-    fn deconstruct(mut self, widgets: &mut HashMap<WidgetKey, WidgetTree>) {
+    fn deconstruct(mut self, widgets: &mut HashMap<WidgetKey, Vec<WidgetTree>>) {
         match self.inner {
             WidgetTreeInner::View(_) => {}
             WidgetTreeInner::Widget(ref mut w) => {
+                w.cache.children = w.children.iter().map(WidgetTree::identity).collect();
                 for child in w.children.drain(..) {
                     child.deconstruct(widgets);
                 }
-                widgets.insert(w.key, self);
+                let key = w.key.clone();
+                widgets.entry(key).or_default().push(self);
             }
             WidgetTreeInner::Layout(ref mut layout) => {
+                layout.cache.children = layout.children.iter().map(WidgetTree::identity).collect();
                 for child in layout.children.drain(..) {
                     child.deconstruct(widgets);
                 }
+                let key = layout.key.clone();
+                widgets.entry(key).or_default().push(self);
             }
         }
     }
 
-    fn find_widget_at(
+    /// Tells trees apart for as long as they exist, as the address of their data stays the same
+    /// when they're moved.
+    fn identity(&self) -> *const () {
+        match self.inner {
+            WidgetTreeInner::View(ref view) => &**view as *const ViewData<dyn DynView> as *const (),
+            WidgetTreeInner::Widget(ref w) => &**w as *const WidgetData<dyn DynWidget> as *const (),
+            WidgetTreeInner::Layout(ref layout) => {
+                &**layout as *const LayoutData<dyn Layout> as *const ()
+            }
+        }
+    }
+
+    /// Whether the tree has been laid out and hasn't changed since.
+    fn is_laid_out(&self) -> bool {
+        match self.inner {
+            WidgetTreeInner::View(ref view) => view.cache.ctx.is_some(),
+            WidgetTreeInner::Widget(ref w) => w.cache.ctx.is_some(),
+            WidgetTreeInner::Layout(ref layout) => layout.cache.ctx.is_some(),
+        }
+    }
+
+    fn find_widget_at<R>(
         &mut self,
         pos: Position,
         user_data: UserDataMut<'_>,
-        func: impl FnOnce(&mut WidgetData<dyn Widget>, UserDataMut<'_>, Position),
-    ) {
+        func: impl FnOnce(&mut WidgetData<dyn DynWidget>, UserDataMut<'_>) -> R,
+    ) -> Option<R> {
         match self.inner {
             WidgetTreeInner::View(ref mut view) => {
                 if let Some(w) = &mut view.widget {
-                    w.find_widget_at(pos, view.user_data.as_deref_mut().or(user_data), func)
+                    w.find_widget_at(pos, Some(view.view.model_mut()), func)
                 } else {
                     panic!("View widget is None when processing event");
                 }
             }
             WidgetTreeInner::Widget(ref mut w) => {
-                // TODO child widgets
-                let rect = w.allocation.unwrap();
-                if rect.contains(pos) {
-                    func(w, user_data, pos - rect.origin);
+                if !w.allocation.unwrap().contains(pos) {
+                    return None;
+                }
+                match w
+                    .children
+                    .iter()
+                    .position(|child| child.rect().contains(pos))
+                {
+                    Some(index) => w.children[index].find_widget_at(pos, user_data, func),
+                    None => Some(func(w, user_data)),
+                }
+            }
+            WidgetTreeInner::Layout(ref mut layout) => layout
+                .children
+                .iter_mut()
+                .find(|child| child.rect().contains(pos))
+                .and_then(|child| child.find_widget_at(pos, user_data, func)),
+        }
+    }
+
+    fn contains_widget(&self, id: WidgetId) -> bool {
+        match self.inner {
+            WidgetTreeInner::View(ref view) => match &view.widget {
+                Some(w) => w.contains_widget(id),
+                None => false,
+            },
+            WidgetTreeInner::Widget(ref w) => {
+                w.id == id || w.children.iter().any(|child| child.contains_widget(id))
+            }
+            WidgetTreeInner::Layout(ref layout) => layout
+                .children
+                .iter()
+                .any(|child| child.contains_widget(id)),
+        }
+    }
+
+    fn find_widget<R>(
+        &mut self,
+        id: WidgetId,
+        user_data: UserDataMut<'_>,
+        func: impl FnOnce(&mut WidgetData<dyn DynWidget>, UserDataMut<'_>) -> R,
+    ) -> Option<R> {
+        match self.inner {
+            WidgetTreeInner::View(ref mut view) => match &mut view.widget {
+                Some(w) => w.find_widget(id, Some(view.view.model_mut()), func),
+                None => None,
+            },
+            WidgetTreeInner::Widget(ref mut w) => {
+                if w.id == id {
+                    Some(func(w, user_data))
+                } else {
+                    w.children
+                        .iter_mut()
+                        .find(|child| child.contains_widget(id))
+                        .and_then(|child| child.find_widget(id, user_data, func))
+                }
+            }
+            WidgetTreeInner::Layout(ref mut layout) => layout
+                .children
+                .iter_mut()
+                .find(|child| child.contains_widget(id))
+                .and_then(|child| child.find_widget(id, user_data, func)),
+        }
+    }
+
+    /// The widget that position events at the given position are sent to.
+    pub(crate) fn widget_at(&mut self, pos: Position) -> Option<WidgetId> {
+        self.find_widget_at(pos, None, |w, _| w.id)
+    }
+
+    fn contains_target(&self, target: Target) -> bool {
+        match target {
+            Target::Position(pos) => self.rect().contains(pos),
+            Target::Widget(id) => self.contains_widget(id),
+        }
+    }
+
+    /// Sends the event along the path to the target. First each ancestor can capture the event
+    /// from the root down, then the event bubbles up from the target until it is handled. Returns
+    /// None if the target isn't within this tree.
+    fn dispatch(
+        &mut self,
+        target: Target,
+        user_data: UserDataMut<'_>,
+        event: Event,
+        window: &mut WindowState,
+    ) -> Option<EventResult> {
+        match self.inner {
+            WidgetTreeInner::View(ref mut view) => {
+                let widget = match &mut view.widget {
+                    Some(w) => w,
+                    None => panic!("View widget is None when processing event"),
+                };
+                if !widget.contains_target(target) {
+                    return None;
+                }
+                let local_event = event.relative_to(widget.rect().origin);
+                if view.view.event(Phase::Capture, local_event) == EventResult::Handled {
+                    return Some(EventResult::Handled);
+                }
+                match widget.dispatch(target, Some(view.view.model_mut()), event, window) {
+                    Some(EventResult::Handled) => Some(EventResult::Handled),
+                    _ => Some(view.view.event(Phase::Bubble, local_event)),
+                }
+            }
+            WidgetTreeInner::Widget(ref mut w) => {
+                let child = match target {
+                    Target::Position(pos) => {
+                        if !w.allocation.unwrap().contains(pos) {
+                            return None;
+                        }
+                        w.children
+                            .iter()
+                            .position(|child| child.rect().contains(pos))
+                    }
+                    Target::Widget(id) if id == w.id => None,
+                    Target::Widget(id) => Some(
+                        w.children
+                            .iter()
+                            .position(|child| child.contains_widget(id))?,
+                    ),
+                };
+                let mut user_data = user_data;
+                if let Some(index) = child {
+                    if w.handle(Phase::Capture, user_data.as_deref_mut(), event, window)
+                        == EventResult::Handled
+                    {
+                        return Some(EventResult::Handled);
+                    }
+                    let child = &mut w.children[index];
+                    if child.dispatch(target, user_data.as_deref_mut(), event, window)
+                        == Some(EventResult::Handled)
+                    {
+                        return Some(EventResult::Handled);
+                    }
                 }
+                Some(w.handle(Phase::Bubble, user_data, event, window))
             }
             WidgetTreeInner::Layout(ref mut layout) => {
-                for child in layout.children.iter_mut() {
-                    if child.rect().contains(pos) {
-                        child.find_widget_at(pos, user_data, func);
-                        break;
+                if let Target::Position(pos) = target {
+                    if !layout.allocation.unwrap().contains(pos) {
+                        return None;
                     }
                 }
+                let child = layout
+                    .children
+                    .iter()
+                    .position(|child| child.contains_target(target));
+                if child.is_none() && matches!(target, Target::Widget(_)) {
+                    return None;
+                }
+                let local_event = event.relative_to(layout.allocation.unwrap().origin);
+                if let Some(index) = child {
+                    if layout.layout.capture_event(local_event) == EventResult::Handled {
+                        return Some(EventResult::Handled);
+                    }
+                    let child = &mut layout.children[index];
+                    if child.dispatch(target, user_data, event, window)
+                        == Some(EventResult::Handled)
+                    {
+                        return Some(EventResult::Handled);
+                    }
+                }
+                Some(layout.layout.event(local_event))
             }
         }
     }
 
-    fn position_event(&mut self, pos: Position, event_constructor: impl FnOnce(Position) -> Event) {
-        self.find_widget_at(pos, None, |w, user_data, pos| {
-            let state = WidgetStateMut {
-                rect: w.allocation.unwrap(),
-                user_data,
-            };
-            w.widget.event(state, event_constructor(pos));
-        });
+    /// Sends the event to the widget with the given id, returns false if the widget is no longer in
+    /// the tree.
+    pub(crate) fn widget_event(
+        &mut self,
+        id: WidgetId,
+        event: Event,
+        window: &mut WindowState,
+    ) -> bool {
+        if event.bubbles() {
+            self.dispatch(Target::Widget(id), None, event, window)
+                .is_some()
+        } else {
+            self.find_widget(id, None, |w, user_data| {
+                w.handle(Phase::Bubble, user_data, event, window);
+            })
+            .is_some()
+        }
     }
 
-    pub(crate) fn event(&mut self, event: Event) {
+    pub(crate) fn event(&mut self, event: Event, window: &mut WindowState) {
         match event {
-            Event::MousePress(pos) => self.position_event(pos, |pos| Event::MousePress(pos)),
-            Event::MouseRelease(pos) => self.position_event(pos, |pos| Event::MouseRelease(pos)),
+            Event::MousePress(MouseEvent { position: pos, .. })
+            | Event::MouseRelease(MouseEvent { position: pos, .. })
+            | Event::MouseMove(pos)
+            | Event::Scroll(ScrollEvent { position: pos, .. }) => {
+                self.dispatch(Target::Position(pos), None, event, window);
+            }
+            Event::KeyDown(_) | Event::KeyUp(_) | Event::Character(_) => {
+                if let Some(id) = window.focus.owner() {
+                    if !self.widget_event(id, event, window) {
+                        window.focus.clear();
+                    }
+                }
+            }
+            // Only sent to a specific widget
+            Event::MouseEnter(_) | Event::MouseLeave | Event::FocusIn | Event::FocusOut => {}
         }
     }
 
-    pub(crate) fn update(&mut self, views: &HashSet<ViewId>, user_data: UserData<'_>) -> bool {
-        match self.inner {
+    /// Rebuilds the views and updates the widgets which need updating, see `Widget::update`.
+    /// Subtrees whose size hint is unchanged are laid out again in place, returns true if the
+    /// tree's size hint changed and so the parent needs to lay it out again.
+    pub(crate) fn update(
+        &mut self,
+        views: &HashSet<ViewId>,
+        widgets: &HashSet<WidgetId>,
+        damage: &mut Damage,
+    ) -> bool {
+        let old_rect = self.rect();
+        let (old_size_hint, ctx) = match self.inner {
             WidgetTreeInner::View(ref mut view) => {
-                let user_data = view.user_data.as_deref().or(user_data);
                 if views.contains(&view.view_id) {
-                    // TODO child widgets{
                     let mut cached = HashMap::new();
                     view.widget.take().map(|tree| tree.deconstruct(&mut cached));
-                    let mut cache = WidgetCache {
-                        factory: WidgetTreeFactory {
-                            app: Arc::clone(&self.app),
-                        },
-                        cached,
-                    };
-                    let mut tree = view.view.view(&mut cache, user_data);
-                    tree.update(views, user_data);
+                    let mut tree = view.build(&self.app, cached);
+                    // Views aren't kept when deconstructing, so any within the tree are new
+                    tree.materialise_views();
                     view.widget = Some(tree);
-                    true
-                } else {
-                    if let Some(w) = &mut view.widget {
-                        w.update(views, user_data)
-                    } else {
-                        panic!("View widget is None when updating views");
+                } else if let Some(w) = &mut view.widget {
+                    if !w.update(views, widgets, damage) {
+                        return false;
                     }
+                } else {
+                    panic!("View widget is None when updating views");
                 }
+                // Views pass the context straight on to their widget, so keep it to lay out the
+                // new widget
+                (view.cache.size_hint.take(), view.cache.ctx)
             }
-            WidgetTreeInner::Widget(_) => {
-                // TODO child widgets
-                false
+            WidgetTreeInner::Widget(ref mut w) => {
+                let mut updated = false;
+                for child in w.children.iter_mut() {
+                    if child.update(views, widgets, damage) {
+                        updated = true
+                    }
+                }
+                // After updating the children, as any new children haven't been laid out yet
+                if widgets.contains(&w.id) && w.widget.update(&mut w.children, &self.app) {
+                    for child in w.children.iter_mut() {
+                        child.materialise_views();
+                    }
+                    updated = true;
+                }
+                if !updated {
+                    return false;
+                }
+                w.cache.take()
             }
             WidgetTreeInner::Layout(ref mut layout) => {
-                // TODO determine whether the update needs to bubble up, i.e. the size hints are the
-                //  same then the parent doesn't need to re-layout.
                 let mut updated = false;
                 for child in layout.children.iter_mut() {
-                    if child.update(views, user_data) {
+                    if child.update(views, widgets, damage) {
                         updated = true
                     }
                 }
-                updated
+                if !updated {
+                    return false;
+                }
+                layout.cache.take()
+            }
+        };
+        match (old_size_hint, ctx) {
+            (Some(old_size_hint), Some(ctx)) if old_size_hint == self.size_hint() => {
+                let rect = self.layout(ctx);
+                damage.add(old_rect);
+                damage.add(rect);
+                false
             }
+            _ => true,
         }
     }
 
@@ -307,18 +864,19 @@ impl WidgetTree {
         match self.inner {
             WidgetTreeInner::View(ref view) => {
                 if let Some(w) = &view.widget {
-                    w.paint(view.user_data.as_deref().or(user_data), painter)
+                    w.paint(Some(view.view.model()), painter)
                 } else {
                     panic!("View widget is None when painting");
                 }
             }
             WidgetTreeInner::Widget(ref w) => {
-                let state = WidgetState {
-                    rect: w.allocation.unwrap(),
-                    user_data,
-                };
-                w.widget
-                    .paint(state, &mut painter.with_rect(w.allocation.unwrap()));
+                let model = user_data.expect("Widget isn't within a view");
+                let opacity = w.widget.opacity();
+                if opacity < 1.0 {
+                    w.paint(model, &mut painter.with_opacity(opacity));
+                } else {
+                    w.paint(model, painter);
+                }
             }
             WidgetTreeInner::Layout(ref layout) => {
                 for child in layout.children.iter() {
@@ -328,7 +886,7 @@ impl WidgetTree {
         }
     }
 
-    fn rect(&self) -> Rect {
+    pub(crate) fn rect(&self) -> Rect {
         match self.inner {
             WidgetTreeInner::View(ref view) => {
                 if let Some(w) = &view.widget {
@@ -342,51 +900,94 @@ impl WidgetTree {
         }
     }
 
-    // TODO should only called during layout
-    pub fn set_rect(&mut self, rect: Rect) {
+    /// Lays out the tree within the context's constraints, returning the rect it now occupies.
+    pub fn layout(&mut self, ctx: LayoutContext) -> Rect {
         match self.inner {
             WidgetTreeInner::View(ref mut view) => {
                 if let Some(w) = &mut view.widget {
-                    w.set_rect(rect);
+                    view.cache.ctx = Some(ctx);
+                    w.layout(ctx)
                 } else {
-                    panic!("View widget is None when setting rect");
+                    panic!("View widget is None when laying out");
                 }
             }
             WidgetTreeInner::Widget(ref mut w) => {
+                if w.cache.ctx == Some(ctx) {
+                    return w.allocation.unwrap();
+                }
+                let size = w.widget.layout(w.children.as_mut_slice(), ctx);
+                let rect = Rect::new(ctx.origin(), ctx.constraints().constrain(size));
                 w.allocation = Some(rect);
+                w.cache.ctx = Some(ctx);
+                rect
             }
             WidgetTreeInner::Layout(ref mut layout) => {
+                if layout.cache.ctx == Some(ctx) {
+                    return layout.allocation.unwrap();
+                }
+                let size = layout.layout.layout(layout.children.as_mut_slice(), ctx);
+                let rect = Rect::new(ctx.origin(), ctx.constraints().constrain(size));
                 layout.allocation = Some(rect);
-                // TODO pass rect origin, probably encapsulate it in a layout context
-                layout
-                    .layout
-                    .layout(layout.children.as_mut_slice(), rect.size);
+                layout.cache.ctx = Some(ctx);
+                rect
             }
         }
     }
 
-    // TODO return min/max size aksi
-    pub fn size_hint(&self) -> Size {
+    pub fn size_hint(&self) -> SizeHint {
         match self.inner {
             WidgetTreeInner::View(ref view) => {
                 if let Some(w) = &view.widget {
-                    w.size_hint()
+                    view.cache.size_hint(|| w.size_hint())
                 } else {
                     panic!("View widget is None when querying size hint");
                 }
             }
-            WidgetTreeInner::Widget(ref w) => w.widget.size_hint(w.children.as_slice()),
+            WidgetTreeInner::Widget(ref w) => w
+                .cache
+                .size_hint(|| w.widget.size_hint(w.children.as_slice())),
+            WidgetTreeInner::Layout(ref layout) => layout
+                .cache
+                .size_hint(|| layout.layout.size_hint(layout.children.as_slice())),
+        }
+    }
+
+    /// Calls the function with the key and rect of each widget and layout in the tree.
+    pub(crate) fn visit_keyed(&self, func: &mut dyn FnMut(&WidgetKey, Rect)) {
+        match self.inner {
+            WidgetTreeInner::View(ref view) => {
+                if let Some(w) = &view.widget {
+                    w.visit_keyed(func);
+                }
+            }
+            WidgetTreeInner::Widget(ref w) => {
+                func(&w.key, w.allocation.unwrap());
+                for child in w.children.iter() {
+                    child.visit_keyed(func);
+                }
+            }
             WidgetTreeInner::Layout(ref layout) => {
-                layout.layout.size_hint(layout.children.as_slice())
+                func(&layout.key, layout.allocation.unwrap());
+                for child in layout.children.iter() {
+                    child.visit_keyed(func);
+                }
             }
         }
     }
 
+    /// The model of the view at the root of the tree, None if the root isn't a view.
+    pub(crate) fn view_model_mut(&mut self) -> Option<&mut dyn Any> {
+        match self.inner {
+            WidgetTreeInner::View(ref mut view) => Some(view.view.model_mut()),
+            _ => None,
+        }
+    }
+
     fn obj_mut(&mut self) -> &mut dyn Any {
         match self.inner {
             WidgetTreeInner::View(_) => panic!(),
             WidgetTreeInner::Widget(ref mut w) => w.widget.as_any_mut(),
-            WidgetTreeInner::Layout(_) => panic!(),
+            WidgetTreeInner::Layout(ref mut layout) => layout.layout.as_any_mut(),
         }
     }
 }
@@ -401,47 +1002,330 @@ impl<T: Any> Downcast for T {
     }
 }
 
-pub trait Widget: Downcast {
-    fn event(&mut self, state: WidgetStateMut<'_>, event: Event);
+/// A widget within a view whose model is `M`, widgets which don't use the model implement this
+/// for any `M`.
+pub trait Widget<M>: Downcast {
+    /// Handles events targeted at the widget, and events bubbling up from its descendants which
+    /// they left unhandled.
+    fn event(&mut self, state: WidgetStateMut<'_, M>, event: Event) -> EventResult;
+
+    /// Called before an event is sent to one of the widget's descendants, handling the event here
+    /// stops it from reaching the descendant.
+    fn capture_event(&mut self, _state: WidgetStateMut<'_, M>, _event: Event) -> EventResult {
+        EventResult::Unhandled
+    }
+
+    fn paint(&self, state: WidgetState<'_, M>, painter: &mut Painter);
+
+    /// Paints on top of the widget's children, which are painted after `paint`.
+    fn paint_over_children(&self, _state: WidgetState<'_, M>, _painter: &mut Painter) {}
+
+    /// How opaque the widget and its children are, from 0 for invisible to 1 for opaque. Below 1
+    /// they're painted into a layer which is faded as a whole, so overlapping parts don't show
+    /// through each other. Call `WidgetStateMut::request_paint` when this changes.
+    fn opacity(&self) -> f32 {
+        1.0
+    }
+
+    fn size_hint(&self, children: &[WidgetTree]) -> SizeHint;
+
+    /// Called when the widget has asked to be updated, e.g. because the model it shows changed
+    /// without its view being rebuilt. Returns true if the widget changed its children, which are
+    /// then laid out again.
+    fn update(&mut self, _children: &mut Vec<WidgetTree>, _factory: &WidgetTreeFactory<M>) -> bool {
+        false
+    }
+
+    /// Lays out the widget's children and returns the widget's size. By default the widget takes
+    /// its preferred size, and its children fill the whole widget.
+    fn layout(&mut self, children: &mut [WidgetTree], ctx: LayoutContext) -> Size {
+        let size = ctx
+            .constraints()
+            .constrain(self.size_hint(children).preferred);
+        for child in children {
+            child.layout(LayoutContext::new(ctx.origin(), Constraints::tight(size)));
+        }
+        size
+    }
+}
+
+/// A widget with the type of its view's model erased, see `DynView`.
+trait DynWidget {
+    fn event(
+        &mut self,
+        phase: Phase,
+        state: WidgetStateMut<'_, dyn Any>,
+        event: Event,
+    ) -> EventResult;
+
+    fn paint(&self, state: WidgetState<'_, dyn Any>, painter: &mut Painter);
+
+    fn paint_over_children(&self, state: WidgetState<'_, dyn Any>, painter: &mut Painter);
+
+    fn opacity(&self) -> f32;
+
+    fn size_hint(&self, children: &[WidgetTree]) -> SizeHint;
+
+    fn update(&mut self, children: &mut Vec<WidgetTree>, app: &Arc<AppInner>) -> bool;
+
+    fn layout(&mut self, children: &mut [WidgetTree], ctx: LayoutContext) -> Size;
+
+    /// The widget itself, for descriptions to apply themselves to.
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+struct WidgetWithModel<W, M> {
+    widget: W,
+    model: PhantomData<fn(&mut M)>,
+}
+
+impl<M: 'static, W: Widget<M>> DynWidget for WidgetWithModel<W, M> {
+    fn event(
+        &mut self,
+        phase: Phase,
+        state: WidgetStateMut<'_, dyn Any>,
+        event: Event,
+    ) -> EventResult {
+        match phase {
+            Phase::Capture => self.widget.capture_event(state.downcast(), event),
+            Phase::Bubble => self.widget.event(state.downcast(), event),
+        }
+    }
+
+    fn paint(&self, state: WidgetState<'_, dyn Any>, painter: &mut Painter) {
+        self.widget.paint(state.downcast(), painter)
+    }
+
+    fn paint_over_children(&self, state: WidgetState<'_, dyn Any>, painter: &mut Painter) {
+        self.widget.paint_over_children(state.downcast(), painter)
+    }
+
+    fn opacity(&self) -> f32 {
+        self.widget.opacity()
+    }
+
+    fn size_hint(&self, children: &[WidgetTree]) -> SizeHint {
+        self.widget.size_hint(children)
+    }
+
+    fn update(&mut self, children: &mut Vec<WidgetTree>, app: &Arc<AppInner>) -> bool {
+        let factory = WidgetTreeFactory::new(Arc::clone(app));
+        self.widget.update(children, &factory)
+    }
+
+    fn layout(&mut self, children: &mut [WidgetTree], ctx: LayoutContext) -> Size {
+        self.widget.layout(children, ctx)
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self.widget.as_any_mut()
+    }
+}
+
+/// What a parent gives to its child when laying out the child.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct LayoutContext {
+    origin: Position,
+    constraints: Constraints,
+}
+
+impl LayoutContext {
+    pub fn new(origin: Position, constraints: Constraints) -> LayoutContext {
+        LayoutContext {
+            origin,
+            constraints,
+        }
+    }
 
-    fn paint(&self, state: WidgetState<'_>, painter: &mut Painter);
+    /// Where the child's top left corner goes, in window coordinates.
+    pub fn origin(&self) -> Position {
+        self.origin
+    }
 
-    fn size_hint(&self, children: &[WidgetTree]) -> Size;
+    pub fn constraints(&self) -> Constraints {
+        self.constraints
+    }
 }
 
-pub trait Layout {
-    fn layout(&self, children: &mut [WidgetTree], size: Size);
+pub trait Layout: Downcast {
+    /// Lays out the children and returns the layout's size, which should satisfy the context's
+    /// constraints.
+    fn layout(&self, children: &mut [WidgetTree], ctx: LayoutContext) -> Size;
 
-    fn size_hint(&self, children: &[WidgetTree]) -> Size;
+    fn size_hint(&self, children: &[WidgetTree]) -> SizeHint;
+
+    /// Handles events bubbling up from the layout's children, see `Widget::event`.
+    fn event(&mut self, _event: Event) -> EventResult {
+        EventResult::Unhandled
+    }
+
+    /// See `Widget::capture_event`.
+    fn capture_event(&mut self, _event: Event) -> EventResult {
+        EventResult::Unhandled
+    }
 }
 
-pub struct WidgetCache {
-    factory: WidgetTreeFactory,
-    cached: HashMap<WidgetKey, WidgetTree>,
+/// Builds the widgets of a view with the given model, reusing the widgets from the view's previous
+/// tree.
+pub struct WidgetCache<M> {
+    factory: WidgetTreeFactory<M>,
+    // Several widgets can have the same key, e.g. when a key without a value is used in a loop
+    cached: HashMap<WidgetKey, Vec<WidgetTree>>,
+    model: PhantomData<fn(&mut M)>,
 }
 
-impl WidgetCache {
-    pub fn build<D: Description>(&mut self, desc: D) -> WidgetTree {
-        match desc.key().and_then(|key| self.cached.remove(&key)) {
+impl<M> WidgetCache<M> {
+    /// A cache for building widgets outside of `View::view`, reusing the widgets in the previous
+    /// tree if there is one.
+    pub fn new(factory: &WidgetTreeFactory<M>, previous: Option<WidgetTree>) -> WidgetCache<M> {
+        let mut cached = HashMap::new();
+        if let Some(previous) = previous {
+            previous.deconstruct(&mut cached);
+        }
+        WidgetCache {
+            factory: WidgetTreeFactory::new(Arc::clone(&factory.app)),
+            cached,
+            model: PhantomData,
+        }
+    }
+
+    pub fn build<D: Description<M>>(&mut self, mut desc: D) -> WidgetTree {
+        let children = desc
+            .children()
+            .into_iter()
+            .map(|child| self.build(child))
+            .collect::<Vec<_>>();
+        let mut tree = match desc.key().and_then(|key| self.take_cached(&key)) {
             Some(mut widget) => match desc.apply(widget.obj_mut()) {
                 Ok(()) => widget,
                 Err(desc) => desc.create(self),
             },
             None => desc.create(self),
+        };
+        // Any previous children were moved into the cache when the tree was deconstructed, and
+        // applying the description may have changed the size hint. Unchanged subtrees keep their
+        // layout, so that only what changed is laid out again.
+        match tree.inner {
+            WidgetTreeInner::View(_) => {}
+            WidgetTreeInner::Widget(ref mut w) => {
+                w.children = children;
+                let (widget, children) = (&w.widget, &w.children);
+                if !w.cache.is_valid(children, || widget.size_hint(children)) {
+                    w.cache = LayoutCache::default();
+                }
+            }
+            WidgetTreeInner::Layout(ref mut layout) => {
+                layout.children = children;
+                let (inner, children) = (&layout.layout, &layout.children);
+                if !layout
+                    .cache
+                    .is_valid(children, || inner.size_hint(children))
+                {
+                    layout.cache = LayoutCache::default();
+                }
+            }
+        }
+        tree
+    }
+
+    /// Takes the widget with the key from the previous tree, widgets sharing a key are reused in
+    /// the order they were in the previous tree.
+    fn take_cached(&mut self, key: &WidgetKey) -> Option<WidgetTree> {
+        let widgets = self.cached.get_mut(key)?;
+        if widgets.is_empty() {
+            None
+        } else {
+            Some(widgets.remove(0))
         }
     }
 
-    pub fn factory(&self) -> &WidgetTreeFactory {
+    pub fn factory(&self) -> &WidgetTreeFactory<M> {
         &self.factory
     }
 }
 
 pub trait View {
-    fn view(&mut self, cache: &mut WidgetCache, user_data: UserData<'_>) -> WidgetTree;
+    /// The view model the view is built from, which is also given to the view's widgets.
+    type Model: ViewModel + 'static;
+
+    fn view(&mut self, cache: &mut WidgetCache<Self::Model>, model: &Self::Model) -> WidgetTree;
+
+    /// Handles events bubbling up from the view's widgets, see `Widget::event`.
+    fn event(&mut self, _event: Event, _model: &mut Self::Model) -> EventResult {
+        EventResult::Unhandled
+    }
+
+    /// See `Widget::capture_event`.
+    fn capture_event(&mut self, _event: Event, _model: &mut Self::Model) -> EventResult {
+        EventResult::Unhandled
+    }
+}
+
+/// A view together with its model, with the model's type erased so that views of different models
+/// can be in the same tree.
+trait DynView {
+    fn view(
+        &mut self,
+        app: &Arc<AppInner>,
+        cached: HashMap<WidgetKey, Vec<WidgetTree>>,
+    ) -> WidgetTree;
+
+    fn event(&mut self, phase: Phase, event: Event) -> EventResult;
+
+    fn model(&self) -> &dyn Any;
+
+    fn model_mut(&mut self) -> &mut dyn Any;
+}
+
+struct ViewWithModel<V: View> {
+    view: V,
+    model: V::Model,
+}
+
+impl<V: View> DynView for ViewWithModel<V> {
+    fn view(
+        &mut self,
+        app: &Arc<AppInner>,
+        cached: HashMap<WidgetKey, Vec<WidgetTree>>,
+    ) -> WidgetTree {
+        let mut cache = WidgetCache {
+            factory: WidgetTreeFactory::new(Arc::clone(app)),
+            cached,
+            model: PhantomData,
+        };
+        self.view.view(&mut cache, &self.model)
+    }
+
+    fn event(&mut self, phase: Phase, event: Event) -> EventResult {
+        match phase {
+            Phase::Capture => self.view.capture_event(event, &mut self.model),
+            Phase::Bubble => self.view.event(event, &mut self.model),
+        }
+    }
+
+    fn model(&self) -> &dyn Any {
+        &self.model
+    }
+
+    fn model_mut(&mut self) -> &mut dyn Any {
+        &mut self.model
+    }
+}
+
+/// A view to update when its view model changes.
+struct ViewRegistration {
+    app: Arc<AppInner>,
+    view_id: ViewId,
+}
+
+thread_local! {
+    /// The view being built on this thread, which observables register when they're read.
+    static BUILDING_VIEW: RefCell<Option<Arc<ViewRegistration>>> = const { RefCell::new(None) };
 }
 
 pub struct ViewRefs {
-    inner: Mutex<Vec<(Arc<AppInner>, ViewId)>>,
+    // The registrations are owned by the views, so they're released when the views are dropped
+    inner: Mutex<Vec<Weak<ViewRegistration>>>,
 }
 
 impl ViewRefs {
@@ -451,14 +1335,219 @@ impl ViewRefs {
         }
     }
 
-    fn init(&self, app: Arc<AppInner>, view_id: ViewId) {
-        self.inner.lock().unwrap().push((app, view_id))
+    fn register(&self, app: Arc<AppInner>, view_id: ViewId) -> Arc<ViewRegistration> {
+        let registration = Arc::new(ViewRegistration { app, view_id });
+        let mut guard = self.inner.lock().unwrap();
+        guard.retain(|registration| registration.strong_count() > 0);
+        guard.push(Arc::downgrade(&registration));
+        registration
+    }
+
+    /// Registers the view being built on this thread, if any, so that it's updated by `update`.
+    pub(crate) fn track_building_view(&self) {
+        BUILDING_VIEW.with(|building| {
+            if let Some(registration) = &*building.borrow() {
+                let mut guard = self.inner.lock().unwrap();
+                let weak = Arc::downgrade(registration);
+                if !guard.iter().any(|existing| existing.ptr_eq(&weak)) {
+                    guard.retain(|registration| registration.strong_count() > 0);
+                    guard.push(weak);
+                }
+            }
+        });
     }
 
     pub fn update(&self) {
-        let guard = self.inner.lock().unwrap();
-        for (app, view_id) in guard.iter() {
-            app.update_view(*view_id);
+        let mut guard = self.inner.lock().unwrap();
+        guard.retain(|registration| match registration.upgrade() {
+            Some(registration) => {
+                registration.app.update_view(registration.view_id);
+                true
+            }
+            None => false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::description::BoxedDescription;
+    use crate::geom::Size;
+    use crate::headless::testing::test_window;
+    use crate::view_model::Observable;
+    use crate::widgets::Button;
+    use crate::{view, widgets, Description, PaintCommand};
+
+    const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+    const BLUE: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+
+    #[derive(ViewModel)]
+    struct Model {
+        view_refs: ViewRefs,
+    }
+
+    /// A half transparent red widget, with a child.
+    struct Faded<M> {
+        child: Option<BoxedDescription<M>>,
+    }
+
+    impl<M: 'static> Description<M> for Faded<M> {
+        fn key(&self) -> Option<WidgetKey> {
+            None
         }
+
+        fn children(&mut self) -> Vec<BoxedDescription<M>> {
+            self.child.take().into_iter().collect()
+        }
+
+        fn apply(self, _: &mut dyn Any) -> Result<(), Self> {
+            Err(self)
+        }
+
+        fn create(self, cache: &mut WidgetCache<M>) -> WidgetTree {
+            cache.factory().new_widget(WidgetKey::caller(), FadedWidget)
+        }
+    }
+
+    struct FadedWidget;
+
+    impl<M> Widget<M> for FadedWidget {
+        fn event(&mut self, _: WidgetStateMut<'_, M>, _: Event) -> EventResult {
+            EventResult::Unhandled
+        }
+
+        fn paint(&self, state: WidgetState<'_, M>, painter: &mut Painter) {
+            painter.paint_quad(state.local_rect(), RED);
+        }
+
+        fn size_hint(&self, _: &[WidgetTree]) -> SizeHint {
+            SizeHint::new(Size::zero(), Size::new(10.0, 10.0), Size::infinite())
+        }
+
+        fn opacity(&self) -> f32 {
+            0.5
+        }
+    }
+
+    #[test]
+    fn opacity_paints_widget_and_children_in_a_layer() {
+        let model = Model {
+            view_refs: ViewRefs::new(),
+        };
+        let mut window = test_window(model, |cache, _| {
+            let button = Button::new(BLUE, |_: &mut Model| {});
+            cache.build(Faded {
+                child: Some(BoxedDescription::new(button)),
+            })
+        });
+        let mut commands: Vec<PaintCommand> = Vec::new();
+        window.paint().replay(&mut commands);
+        // Leaving out the button's empty text
+        commands.retain(|command| !matches!(command, PaintCommand::Text { .. }));
+        let rect = Rect::new(Position::zero(), window.size());
+        assert_eq!(
+            commands,
+            vec![
+                PaintCommand::PushLayer { opacity: 0.5 },
+                PaintCommand::Quad { rect, colour: RED },
+                PaintCommand::Quad { rect, colour: BLUE },
+                PaintCommand::PopLayer,
+            ]
+        );
+    }
+
+    #[derive(ViewModel)]
+    struct Toggle {
+        view_refs: ViewRefs,
+        on: Observable<bool>,
+    }
+
+    /// Counts how many times it's laid out.
+    #[derive(Description)]
+    #[description(widget = "CountingWidget", model = "Toggle")]
+    struct Counting {
+        layouts: Rc<Cell<u32>>,
+        key: WidgetKey,
+    }
+
+    impl Counting {
+        #[track_caller]
+        fn new(layouts: Rc<Cell<u32>>) -> Counting {
+            Counting {
+                layouts,
+                key: WidgetKey::caller(),
+            }
+        }
+    }
+
+    struct CountingWidget {
+        layouts: Rc<Cell<u32>>,
+    }
+
+    impl From<Counting> for CountingWidget {
+        fn from(counting: Counting) -> Self {
+            CountingWidget {
+                layouts: counting.layouts,
+            }
+        }
+    }
+
+    impl Widget<Toggle> for CountingWidget {
+        fn event(&mut self, _: WidgetStateMut<'_, Toggle>, _: Event) -> EventResult {
+            EventResult::Unhandled
+        }
+
+        fn paint(&self, _: WidgetState<'_, Toggle>, _: &mut Painter) {}
+
+        fn size_hint(&self, _: &[WidgetTree]) -> SizeHint {
+            SizeHint::new(Size::zero(), Size::new(10.0, 10.0), Size::infinite())
+        }
+
+        fn layout(&mut self, _: &mut [WidgetTree], _: LayoutContext) -> Size {
+            self.layouts.set(self.layouts.get() + 1);
+            Size::new(10.0, 10.0)
+        }
+    }
+
+    #[test]
+    fn rebuilding_keeps_the_layout_of_unchanged_subtrees() {
+        let layouts = Rc::new(Cell::new(0));
+        let model = Toggle {
+            view_refs: ViewRefs::new(),
+            on: Observable::new(false),
+        };
+        let counted = Rc::clone(&layouts);
+        let mut window = test_window(model, move |cache, model: &Toggle| {
+            let colour = if model.on.get() { RED } else { BLUE };
+            cache.build(view! {
+                widgets::Box {
+                    Counting(Rc::clone(&counted))
+                    widgets::Button(colour, |_: &mut Toggle| {})
+                }
+            })
+        });
+        assert_eq!(layouts.get(), 1);
+
+        window.model_mut().on.set(true);
+        assert!(window.update());
+        // Only the button's colour changed, which doesn't change its size
+        assert_eq!(layouts.get(), 1);
+        let mut commands: Vec<PaintCommand> = Vec::new();
+        window.paint().replay(&mut commands);
+        assert!(commands.iter().any(|command| matches!(
+            command,
+            PaintCommand::Quad { colour, .. } if *colour == RED
+        )));
+    }
+
+    #[test]
+    fn signed_key_values_are_distinct_from_unsigned() {
+        assert_ne!(KeyValue::from(-1i64), KeyValue::from(u64::MAX));
+        assert_ne!(KeyValue::from(-1i32), KeyValue::from(u32::MAX));
+        assert_eq!(KeyValue::from(-1i32), KeyValue::from(-1i64));
+        assert_eq!(KeyValue::from(5i32), KeyValue::from(5u64));
     }
 }
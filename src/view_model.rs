@@ -1,5 +1,272 @@
-use crate::view::ViewRefs;
+use std::sync::{Arc, Mutex, Weak};
+
+use crate::app::AppInner;
+use crate::view::{ViewRefs, WidgetId};
+
+pub use kakapo_derive::ViewModel;
 
 pub trait ViewModel {
     fn view_refs(&self) -> &ViewRefs;
 }
+
+/// A value within a view model which keeps track of the views that read it while they're built,
+/// and updates those views whenever the value is written.
+pub struct Observable<T> {
+    value: Mutex<T>,
+    view_refs: ViewRefs,
+}
+
+impl<T> Observable<T> {
+    pub fn new(value: T) -> Observable<T> {
+        Observable {
+            value: Mutex::new(value),
+            view_refs: ViewRefs::new(),
+        }
+    }
+
+    pub fn get(&self) -> T
+    where
+        T: Clone,
+    {
+        self.with(T::clone)
+    }
+
+    /// Reads the value. When called from `View::view` the view is rebuilt whenever the value is
+    /// next written.
+    pub fn with<R>(&self, func: impl FnOnce(&T) -> R) -> R {
+        self.view_refs.track_building_view();
+        func(&self.value.lock().unwrap())
+    }
+
+    pub fn set(&self, value: T) {
+        self.modify(|current| *current = value)
+    }
+
+    /// Changes the value in place, then updates the views which read it.
+    pub fn modify<R>(&self, func: impl FnOnce(&mut T) -> R) -> R {
+        let result = func(&mut self.value.lock().unwrap());
+        self.view_refs.update();
+        result
+    }
+}
+
+impl<T: Default> Default for Observable<T> {
+    fn default() -> Observable<T> {
+        Observable::new(T::default())
+    }
+}
+
+/// A change to an `ObservableVec`, with indices as they were when the change was made.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ListChange {
+    Insert(usize),
+    Remove(usize),
+    /// The item is removed from `from` then inserted at `to`.
+    Move {
+        from: usize,
+        to: usize,
+    },
+    /// The item at the index was replaced or changed in place.
+    Change(usize),
+}
+
+struct VecInner<T> {
+    items: Mutex<Vec<T>>,
+    subscribers: Mutex<Vec<Weak<Subscriber>>>,
+}
+
+struct Subscriber {
+    changes: Mutex<Vec<ListChange>>,
+    // The widget to update when there are changes, if any
+    widget: Mutex<Option<(Arc<AppInner>, WidgetId)>>,
+}
+
+/// A list within a view model, like `Observable` views which read the whole list are updated when
+/// it's written. Widgets showing the list can instead subscribe to the individual changes, so that
+/// they only update the items which changed.
+pub struct ObservableVec<T> {
+    inner: Arc<VecInner<T>>,
+    view_refs: ViewRefs,
+}
+
+impl<T> ObservableVec<T> {
+    pub fn new(items: Vec<T>) -> ObservableVec<T> {
+        ObservableVec {
+            inner: Arc::new(VecInner {
+                items: Mutex::new(items),
+                subscribers: Mutex::new(Vec::new()),
+            }),
+            view_refs: ViewRefs::new(),
+        }
+    }
+
+    /// Reads the items, see `Observable::with`.
+    pub fn with<R>(&self, func: impl FnOnce(&[T]) -> R) -> R {
+        self.view_refs.track_building_view();
+        func(&self.inner.items.lock().unwrap())
+    }
+
+    pub fn len(&self) -> usize {
+        self.with(|items| items.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<T>
+    where
+        T: Clone,
+    {
+        self.with(|items| items.get(index).cloned())
+    }
+
+    /// Subscribes to the changes made from now on, which are relative to the items given to
+    /// `func`. Reading the items this way doesn't update the view being built.
+    pub fn subscribe<R>(&self, func: impl FnOnce(&[T]) -> R) -> (ListSubscription<T>, R) {
+        let items = self.inner.items.lock().unwrap();
+        let subscriber = Arc::new(Subscriber {
+            changes: Mutex::new(Vec::new()),
+            widget: Mutex::new(None),
+        });
+        let mut subscribers = self.inner.subscribers.lock().unwrap();
+        subscribers.retain(|subscriber| subscriber.strong_count() > 0);
+        subscribers.push(Arc::downgrade(&subscriber));
+        drop(subscribers);
+        let result = func(&items);
+        let subscription = ListSubscription {
+            vec: Arc::clone(&self.inner),
+            subscriber,
+        };
+        (subscription, result)
+    }
+
+    pub fn push(&self, item: T) {
+        self.change(|items| {
+            items.push(item);
+            ListChange::Insert(items.len() - 1)
+        })
+    }
+
+    pub fn insert(&self, index: usize, item: T) {
+        self.change(|items| {
+            items.insert(index, item);
+            ListChange::Insert(index)
+        })
+    }
+
+    pub fn remove(&self, index: usize) -> T {
+        let mut removed = None;
+        self.change(|items| {
+            removed = Some(items.remove(index));
+            ListChange::Remove(index)
+        });
+        removed.unwrap()
+    }
+
+    pub fn move_item(&self, from: usize, to: usize) {
+        self.change(|items| {
+            let item = items.remove(from);
+            items.insert(to, item);
+            ListChange::Move { from, to }
+        })
+    }
+
+    pub fn set(&self, index: usize, item: T) {
+        self.modify(index, |current| *current = item)
+    }
+
+    /// Changes the item in place.
+    pub fn modify(&self, index: usize, func: impl FnOnce(&mut T)) {
+        self.change(|items| {
+            func(&mut items[index]);
+            ListChange::Change(index)
+        })
+    }
+
+    /// Makes a change while the items are locked, so that subscribers see the changes in the same
+    /// order as they're made.
+    fn change(&self, func: impl FnOnce(&mut Vec<T>) -> ListChange) {
+        let mut items = self.inner.items.lock().unwrap();
+        let change = func(&mut items);
+        let mut to_update = Vec::new();
+        let mut subscribers = self.inner.subscribers.lock().unwrap();
+        subscribers.retain(|subscriber| match subscriber.upgrade() {
+            Some(subscriber) => {
+                subscriber.changes.lock().unwrap().push(change);
+                if let Some((app, id)) = &*subscriber.widget.lock().unwrap() {
+                    to_update.push((Arc::clone(app), *id));
+                }
+                true
+            }
+            None => false,
+        });
+        drop(subscribers);
+        drop(items);
+        for (app, id) in to_update {
+            app.update_widget(id);
+        }
+        self.view_refs.update();
+    }
+}
+
+impl<T> Default for ObservableVec<T> {
+    fn default() -> ObservableVec<T> {
+        ObservableVec::new(Vec::new())
+    }
+}
+
+/// The changes made to an `ObservableVec` since subscribing, the subscription ends when this is
+/// dropped.
+pub struct ListSubscription<T> {
+    vec: Arc<VecInner<T>>,
+    subscriber: Arc<Subscriber>,
+}
+
+impl<T> ListSubscription<T> {
+    /// Takes the changes made since they were last taken, giving them to `func` along with the
+    /// items after those changes.
+    pub fn take_changes<R>(&self, func: impl FnOnce(&[ListChange], &[T]) -> R) -> R {
+        let items = self.vec.items.lock().unwrap();
+        let changes = std::mem::take(&mut *self.subscriber.changes.lock().unwrap());
+        func(&changes, &items)
+    }
+
+    /// Updates the widget whenever there are new changes, and straight away if changes were made
+    /// before the widget subscribed.
+    pub(crate) fn notify_widget(&self, app: &Arc<AppInner>, id: WidgetId) {
+        *self.subscriber.widget.lock().unwrap() = Some((Arc::clone(app), id));
+        // After setting the widget, so that a change made meanwhile updates it one way or the other
+        if !self.subscriber.changes.lock().unwrap().is_empty() {
+            app.update_widget(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notify_widget_updates_for_earlier_changes() {
+        let app = Arc::new(AppInner::new(None));
+        let vec = ObservableVec::new(vec![1, 2]);
+        let (subscription, _) = vec.subscribe(|_| ());
+        vec.push(3);
+        let id = app.new_widget_id();
+        subscription.notify_widget(&app, id);
+        assert!(app.take_updates().1.contains(&id));
+    }
+
+    #[test]
+    fn notify_widget_waits_for_changes() {
+        let app = Arc::new(AppInner::new(None));
+        let vec = ObservableVec::new(vec![1, 2]);
+        let (subscription, _) = vec.subscribe(|_| ());
+        let id = app.new_widget_id();
+        subscription.notify_widget(&app, id);
+        assert!(app.take_updates().1.is_empty());
+        vec.push(3);
+        assert!(app.take_updates().1.contains(&id));
+    }
+}
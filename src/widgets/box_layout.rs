@@ -1,69 +1,185 @@
 use std::any::Any;
 
 use crate::description::BoxedDescription;
-use crate::geom::{Position, Rect, Size};
-use crate::view::{Layout, WidgetCache, WidgetKey, WidgetTree};
+use crate::geom::{Constraints, Position, Scalar, Size, SizeHint};
+use crate::view::{KeyValue, Layout, LayoutContext, WidgetCache, WidgetKey, WidgetTree};
 use crate::Description;
 
-pub struct Box {
-    widgets: Vec<BoxedDescription>,
+/// Stacks its children vertically.
+pub struct Box<M> {
+    widgets: Vec<BoxedDescription<M>>,
+    key: WidgetKey,
 }
 
-impl Box {
-    pub fn new() -> Box {
+impl<M: 'static> Box<M> {
+    #[track_caller]
+    pub fn new() -> Box<M> {
         Box {
             widgets: Vec::new(),
+            key: WidgetKey::caller(),
         }
     }
 
-    pub fn append<D: Description + 'static>(mut self, desc: D) -> Self {
+    pub fn append<D: Description<M> + 'static>(mut self, desc: D) -> Self {
         self.widgets.push(BoxedDescription::new(desc));
         self
     }
+
+    /// See `Button::with_key`.
+    pub fn with_key(mut self, value: impl Into<KeyValue>) -> Self {
+        self.key = self.key.with_value(value);
+        self
+    }
 }
 
-impl Description for Box {
+impl<M> Description<M> for Box<M> {
     fn key(&self) -> Option<WidgetKey> {
-        None
+        Some(self.key.clone())
     }
 
-    fn apply(self, _: &mut dyn Any) -> Result<(), Self>
-    where
-        Self: Sized,
-    {
-        panic!("Box can't be persisted")
+    fn children(&mut self) -> Vec<BoxedDescription<M>> {
+        std::mem::take(&mut self.widgets)
     }
 
-    fn create(self, cache: &mut WidgetCache) -> WidgetTree {
-        let children = self
-            .widgets
-            .into_iter()
-            .map(|desc| cache.build(desc))
-            .collect::<Vec<_>>();
-        cache.factory().new_layout(BoxLayout {}, children)
+    fn apply(self, obj: &mut dyn Any) -> Result<(), Self> {
+        // The children are reconciled by the cache, and the box has no other state
+        match obj.downcast_mut::<BoxLayout>() {
+            Some(_) => Ok(()),
+            None => Err(self),
+        }
+    }
+
+    fn create(self, cache: &mut WidgetCache<M>) -> WidgetTree {
+        cache.factory().new_layout(self.key, BoxLayout {})
     }
 }
 
-struct BoxLayout {}
+pub(crate) struct BoxLayout {}
+
+/// Splits the available length between children, starting from their preferred lengths and then
+/// growing them towards their max or shrinking them towards their min.
+fn distribute(available: Scalar, hints: &[(Scalar, Scalar, Scalar)]) -> Vec<Scalar> {
+    let mut lengths = hints
+        .iter()
+        .map(|&(_, preferred, _)| preferred)
+        .collect::<Vec<_>>();
+    let total: Scalar = lengths.iter().sum();
+    if available >= total {
+        // Share the extra space equally between children which can still grow, repeating as
+        // children reach their max
+        let mut extra = available - total;
+        loop {
+            let growable = (0..hints.len())
+                .filter(|&i| lengths[i] < hints[i].2)
+                .collect::<Vec<_>>();
+            if growable.is_empty() || extra <= Scalar::EPSILON {
+                break;
+            }
+            let share = extra / growable.len() as Scalar;
+            let mut grew = false;
+            for i in growable {
+                let grown = (lengths[i] + share).min(hints[i].2);
+                grew |= grown > lengths[i];
+                extra -= grown - lengths[i];
+                lengths[i] = grown;
+            }
+            // Once only rounding error is left the share is too small to change any length
+            if !grew {
+                break;
+            }
+        }
+    } else {
+        // Shrink in proportion to how much each child can shrink
+        let shrinkable: Scalar = hints
+            .iter()
+            .map(|&(min, preferred, _)| preferred - min)
+            .sum();
+        if shrinkable > 0.0 {
+            let ratio = ((total - available) / shrinkable).min(1.0);
+            for (length, &(min, preferred, _)) in lengths.iter_mut().zip(hints) {
+                *length = preferred - (preferred - min) * ratio;
+            }
+        }
+    }
+    lengths
+}
 
 impl Layout for BoxLayout {
-    fn layout(&self, children: &mut [WidgetTree], _: Size) {
-        let mut y = 0f32;
-        for child in children {
-            let size = child.size_hint();
-            child.set_rect(Rect::new(Position::new(0.0, y), size));
-            y += size.height;
+    fn layout(&self, children: &mut [WidgetTree], ctx: LayoutContext) -> Size {
+        let size = ctx
+            .constraints()
+            .constrain(self.size_hint(children).preferred);
+        let hints = children
+            .iter()
+            .map(|child| child.size_hint())
+            .collect::<Vec<_>>();
+        let heights = distribute(
+            size.height,
+            &hints
+                .iter()
+                .map(|hint| (hint.min.height, hint.preferred.height, hint.max.height))
+                .collect::<Vec<_>>(),
+        );
+
+        let origin = ctx.origin();
+        let mut y = origin.y;
+        for ((child, hint), height) in children.iter_mut().zip(hints).zip(heights) {
+            // Children are stretched to the width of the box, as far as they allow
+            let width = size.width.min(hint.max.width).max(hint.min.width);
+            let child_size = Size::new(width, height);
+            child.layout(LayoutContext::new(
+                Position::new(origin.x, y),
+                Constraints::tight(child_size),
+            ));
+            y += height;
         }
+        size
     }
 
-    fn size_hint(&self, children: &[WidgetTree]) -> Size {
-        let mut height = 0f32;
-        let mut width = 0f32;
+    fn size_hint(&self, children: &[WidgetTree]) -> SizeHint {
+        let mut hint = SizeHint::fixed(Size::zero());
         for child in children {
-            let size = child.size_hint();
-            height += size.height;
-            width = width.max(size.width);
+            let child_hint = child.size_hint();
+            hint.min.height += child_hint.min.height;
+            hint.preferred.height += child_hint.preferred.height;
+            hint.max.height += child_hint.max.height;
+            hint.min.width = hint.min.width.max(child_hint.min.width);
+            hint.preferred.width = hint.preferred.width.max(child_hint.preferred.width);
+            hint.max.width = hint.max.width.max(child_hint.max.width);
+        }
+        hint
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distribute_grows_towards_max() {
+        let lengths = distribute(100.0, &[(0.0, 10.0, 20.0), (0.0, 10.0, Scalar::INFINITY)]);
+        assert_eq!(lengths, vec![20.0, 80.0]);
+    }
+
+    #[test]
+    fn distribute_shrinks_towards_min() {
+        let lengths = distribute(25.0, &[(10.0, 20.0, 20.0), (0.0, 20.0, 20.0)]);
+        // The second child can shrink twice as far, so it shrinks twice as much
+        assert_eq!(lengths, vec![15.0, 10.0]);
+    }
+
+    #[test]
+    fn distribute_stops_with_fractional_sizes() {
+        let hints = [(40.0, 100.0, Scalar::INFINITY); 3];
+        for step in 0..2000 {
+            let available = 300.0 + step as Scalar * 0.37;
+            let total: Scalar = distribute(available, &hints).iter().sum();
+            assert!(
+                (total - available).abs() < 0.01,
+                "{} != {}",
+                total,
+                available
+            );
         }
-        Size::new(width, height)
     }
 }
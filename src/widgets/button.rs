@@ -1,27 +1,39 @@
-use std::any::Any;
+use std::marker::PhantomData;
 
 use glyph_brush::{OwnedText, Text};
 
-use crate::events::Event;
-use crate::geom::Size;
+use crate::events::{Event, EventResult, KeyEvent, MouseButton, MouseEvent, VirtualKeyCode};
+use crate::geom::{Scalar, Size, SizeHint};
 use crate::renderer::painter::Painter;
-use crate::view::{
-    UserDataMut, Widget, WidgetCache, WidgetKey, WidgetState, WidgetStateMut, WidgetTree,
-};
+use crate::view::{KeyValue, Widget, WidgetKey, WidgetState, WidgetStateMut, WidgetTree};
 use crate::Description;
 
-pub trait ButtonDelegate {
-    fn pressed(&mut self, parent: UserDataMut<'_>);
+/// Handles presses of a button within a view of the model `M`.
+pub trait ButtonDelegate<M> {
+    fn pressed(&mut self, model: &mut M);
 }
 
-pub struct Button<D: ButtonDelegate + 'static> {
+/// Closures taking the model can handle presses, e.g. `|model: &mut MyModel| ...`.
+impl<M, F: FnMut(&mut M)> ButtonDelegate<M> for F {
+    fn pressed(&mut self, model: &mut M) {
+        self(model)
+    }
+}
+
+#[derive(Description)]
+#[description(
+    widget = "ButtonWidget<M, D>",
+    model_param = "M",
+    bound = "M: 'static, D: ButtonDelegate<M> + 'static"
+)]
+pub struct Button<D> {
     colour: [f32; 4],
     text: Vec<OwnedText>,
     delegate: D,
     key: WidgetKey,
 }
 
-impl<D: ButtonDelegate + 'static> Button<D> {
+impl<D> Button<D> {
     #[track_caller]
     pub fn new(colour: [f32; 4], delegate: D) -> Self {
         Button {
@@ -33,58 +45,104 @@ impl<D: ButtonDelegate + 'static> Button<D> {
     }
 }
 
-impl<D: ButtonDelegate + 'static> Button<D> {
+impl<D> Button<D> {
     pub fn add_text(mut self, text: impl Into<OwnedText>) -> Self {
         self.text.push(text.into());
         self
     }
-}
 
-impl<D: ButtonDelegate + 'static> Description for Button<D> {
-    fn key(&self) -> Option<WidgetKey> {
-        Some(self.key)
-    }
-
-    fn apply(self, obj: &mut dyn Any) -> Result<(), Self> {
-        match obj.downcast_mut::<ButtonWidget<D>>() {
-            Some(widget) => {
-                widget.colour = self.colour;
-                widget.text = self.text;
-                widget.delegate = self.delegate;
-                Ok(())
-            }
-            None => Err(self),
-        }
-    }
-
-    fn create(self, cache: &mut WidgetCache) -> WidgetTree {
-        cache.factory().new_widget(
-            self.key,
-            ButtonWidget {
-                colour: self.colour,
-                text: self.text,
-                delegate: self.delegate,
-            },
-        )
+    /// Keeps the button's state when it's one of several buttons created in a loop, see
+    /// `WidgetKey::with_value`.
+    pub fn with_key(mut self, value: impl Into<KeyValue>) -> Self {
+        self.key = self.key.with_value(value);
+        self
     }
 }
 
-struct ButtonWidget<D: ButtonDelegate> {
+struct ButtonWidget<M, D> {
     colour: [f32; 4],
     text: Vec<OwnedText>,
     delegate: D,
+    hovered: bool,
+    pressed: bool,
+    model: PhantomData<fn(&mut M)>,
 }
 
-impl<D: ButtonDelegate + 'static> Widget for ButtonWidget<D> {
-    fn event(&mut self, mut state: WidgetStateMut<'_>, event: Event) {
+impl<M, D> From<Button<D>> for ButtonWidget<M, D> {
+    fn from(button: Button<D>) -> Self {
+        ButtonWidget {
+            colour: button.colour,
+            text: button.text,
+            delegate: button.delegate,
+            hovered: false,
+            pressed: false,
+            model: PhantomData,
+        }
+    }
+}
+
+impl<M, D> ButtonWidget<M, D> {
+    fn current_colour(&self) -> [f32; 4] {
+        if self.hovered {
+            // Lighten towards white
+            let [r, g, b, a] = self.colour;
+            [
+                r + (1.0 - r) * 0.25,
+                g + (1.0 - g) * 0.25,
+                b + (1.0 - b) * 0.25,
+                a,
+            ]
+        } else {
+            self.colour
+        }
+    }
+}
+
+impl<M: 'static, D: ButtonDelegate<M> + 'static> Widget<M> for ButtonWidget<M, D> {
+    fn event(&mut self, mut state: WidgetStateMut<'_, M>, event: Event) -> EventResult {
         match event {
-            Event::MousePress(_) => self.delegate.pressed(state.user_data()),
-            _ => {}
+            Event::MousePress(MouseEvent {
+                button: MouseButton::Left,
+                ..
+            }) => {
+                state.request_focus();
+                self.pressed = true;
+            }
+            // Only counts as a press if the mouse is released within the button, the release is
+            // received either way as the button captures the mouse
+            Event::MouseRelease(MouseEvent {
+                position,
+                button: MouseButton::Left,
+                ..
+            }) => {
+                if self.pressed && state.local_rect().contains(position) {
+                    self.delegate.pressed(state.model_mut());
+                }
+                self.pressed = false;
+            }
+            Event::KeyDown(KeyEvent {
+                key: Some(VirtualKeyCode::Return),
+                ..
+            })
+            | Event::KeyDown(KeyEvent {
+                key: Some(VirtualKeyCode::Space),
+                ..
+            }) => self.delegate.pressed(state.model_mut()),
+            Event::MouseEnter(_) => {
+                self.hovered = true;
+                state.request_paint();
+            }
+            Event::MouseLeave => {
+                self.hovered = false;
+                state.request_paint();
+            }
+            _ => return EventResult::Unhandled,
         }
+        EventResult::Handled
     }
 
-    fn paint(&self, state: WidgetState<'_>, painter: &mut Painter) {
-        painter.paint_quad(state.local_rect(), self.colour);
+    fn paint(&self, state: WidgetState<'_, M>, painter: &mut Painter) {
+        painter.paint_quad(state.local_rect(), self.current_colour());
         painter.paint_text(
             wgpu_glyph::Section::default()
                 .with_text(self.text.iter().map(Text::from).collect())
@@ -97,7 +155,11 @@ impl<D: ButtonDelegate + 'static> Widget for ButtonWidget<D> {
         );
     }
 
-    fn size_hint(&self, _: &[WidgetTree]) -> Size {
-        Size::new(100.0, 100.0)
+    fn size_hint(&self, _: &[WidgetTree]) -> SizeHint {
+        SizeHint::new(
+            Size::new(40.0, 40.0),
+            Size::new(100.0, 100.0),
+            Size::new(Scalar::INFINITY, 100.0),
+        )
     }
 }
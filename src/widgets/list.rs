@@ -0,0 +1,254 @@
+use std::any::Any;
+use std::sync::Arc;
+
+use crate::app::AppInner;
+use crate::description::BoxedDescription;
+use crate::events::{Event, EventResult};
+use crate::geom::{Size, SizeHint};
+use crate::renderer::painter::Painter;
+use crate::view::{
+    KeyValue, Layout, LayoutContext, Widget, WidgetCache, WidgetId, WidgetKey, WidgetState,
+    WidgetStateMut, WidgetTree, WidgetTreeFactory,
+};
+use crate::view_model::{ListChange, ListSubscription, ObservableVec};
+use crate::widgets::box_layout::BoxLayout;
+use crate::Description;
+
+type RowFn<M, T> = Box<dyn Fn(&T) -> BoxedDescription<M>>;
+
+/// Stacks a row for each item of an `ObservableVec` vertically. When the items change only the
+/// rows of the changed items are built again, without rebuilding the view.
+pub struct List<M, T> {
+    subscription: ListSubscription<T>,
+    row: RowFn<M, T>,
+    // Only empty once taken by the cache
+    rows: Vec<BoxedDescription<M>>,
+    key: WidgetKey,
+}
+
+impl<M: 'static, T: 'static> List<M, T> {
+    #[track_caller]
+    pub fn new<D: Description<M> + 'static>(
+        items: &ObservableVec<T>,
+        row: impl Fn(&T) -> D + 'static,
+    ) -> List<M, T> {
+        let row: RowFn<M, T> = Box::new(move |item| BoxedDescription::new(row(item)));
+        let (subscription, rows) = items.subscribe(|items| items.iter().map(&row).collect());
+        List {
+            subscription,
+            row,
+            rows,
+            key: WidgetKey::caller(),
+        }
+    }
+
+    /// See `Button::with_key`.
+    pub fn with_key(mut self, value: impl Into<KeyValue>) -> Self {
+        self.key = self.key.with_value(value);
+        self
+    }
+}
+
+impl<M: 'static, T: 'static> Description<M> for List<M, T> {
+    fn key(&self) -> Option<WidgetKey> {
+        Some(self.key.clone())
+    }
+
+    fn children(&mut self) -> Vec<BoxedDescription<M>> {
+        std::mem::take(&mut self.rows)
+    }
+
+    fn apply(self, obj: &mut dyn Any) -> Result<(), Self> {
+        match obj.downcast_mut::<ListWidget<M, T>>() {
+            Some(widget) => {
+                // The rows were built again from the new subscription's items, so its changes
+                // replace any the widget hadn't applied yet
+                self.subscription.notify_widget(&widget.app, widget.id);
+                widget.subscription = self.subscription;
+                widget.row = self.row;
+                Ok(())
+            }
+            None => Err(self),
+        }
+    }
+
+    fn create(self, cache: &mut WidgetCache<M>) -> WidgetTree {
+        let subscription = self.subscription;
+        let row = self.row;
+        cache
+            .factory()
+            .new_widget_with_id(self.key, move |app, id| {
+                subscription.notify_widget(app, id);
+                ListWidget {
+                    app: Arc::clone(app),
+                    id,
+                    subscription,
+                    row,
+                }
+            })
+    }
+}
+
+struct ListWidget<M, T> {
+    app: Arc<AppInner>,
+    id: WidgetId,
+    subscription: ListSubscription<T>,
+    row: RowFn<M, T>,
+}
+
+/// A row of the list while applying changes.
+enum Row {
+    Unchanged(WidgetTree),
+    Changed(WidgetTree),
+    Inserted,
+}
+
+impl<M: 'static, T: 'static> Widget<M> for ListWidget<M, T> {
+    fn event(&mut self, _: WidgetStateMut<'_, M>, _: Event) -> EventResult {
+        EventResult::Unhandled
+    }
+
+    fn paint(&self, _: WidgetState<'_, M>, _: &mut Painter) {}
+
+    fn size_hint(&self, children: &[WidgetTree]) -> SizeHint {
+        BoxLayout {}.size_hint(children)
+    }
+
+    fn update(&mut self, children: &mut Vec<WidgetTree>, factory: &WidgetTreeFactory<M>) -> bool {
+        let row = &self.row;
+        self.subscription.take_changes(|changes, items| {
+            if changes.is_empty() {
+                return false;
+            }
+            let mut rows = children.drain(..).map(Row::Unchanged).collect::<Vec<_>>();
+            for change in changes {
+                match *change {
+                    ListChange::Insert(index) => rows.insert(index, Row::Inserted),
+                    ListChange::Remove(index) => {
+                        rows.remove(index);
+                    }
+                    ListChange::Move { from, to } => {
+                        let moved = rows.remove(from);
+                        rows.insert(to, moved);
+                    }
+                    ListChange::Change(index) => {
+                        rows[index] = match std::mem::replace(&mut rows[index], Row::Inserted) {
+                            Row::Unchanged(tree) | Row::Changed(tree) => Row::Changed(tree),
+                            Row::Inserted => Row::Inserted,
+                        }
+                    }
+                }
+            }
+            debug_assert_eq!(
+                rows.len(),
+                items.len(),
+                "List changes don't match its items"
+            );
+            // Each row is rebuilt with its own cache, as the rows' widgets share keys
+            *children = rows
+                .into_iter()
+                .zip(items)
+                .map(|(tree, item)| match tree {
+                    Row::Unchanged(tree) => tree,
+                    Row::Changed(tree) => WidgetCache::new(factory, Some(tree)).build(row(item)),
+                    Row::Inserted => WidgetCache::new(factory, None).build(row(item)),
+                })
+                .collect();
+            true
+        })
+    }
+
+    fn layout(&mut self, children: &mut [WidgetTree], ctx: LayoutContext) -> Size {
+        BoxLayout {}.layout(children, ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::testing::test_window;
+    use crate::headless::HeadlessWindow;
+    use crate::view::ViewRefs;
+    use crate::view_model::ViewModel;
+    use crate::widgets::Button;
+
+    #[derive(ViewModel)]
+    struct Items {
+        view_refs: ViewRefs,
+        items: ObservableVec<u64>,
+    }
+
+    /// A window with a row for each item, keyed by the item.
+    fn window(items: Vec<u64>) -> HeadlessWindow<Items> {
+        let model = Items {
+            view_refs: ViewRefs::new(),
+            items: ObservableVec::new(items),
+        };
+        test_window(model, |cache, model: &Items| {
+            cache.build(List::new(&model.items, |item: &u64| {
+                Button::new([0.0, 0.0, 0.0, 1.0], |_: &mut Items| {}).with_key(*item)
+            }))
+        })
+    }
+
+    /// Asserts that there's a row for each item, in the given order from the top.
+    fn assert_rows(window: &HeadlessWindow<Items>, items: &[u64]) {
+        let tops = items
+            .iter()
+            .map(|item| {
+                let rects = window.rects_with_key(*item);
+                assert_eq!(rects.len(), 1, "rows of item {}", item);
+                rects[0].origin.y
+            })
+            .collect::<Vec<_>>();
+        assert!(tops.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", tops);
+    }
+
+    #[test]
+    fn update_inserts_rows() {
+        let mut window = window(vec![1, 2]);
+        window.model_mut().items.insert(1, 3);
+        window.model_mut().items.push(4);
+        assert!(window.update());
+        assert_rows(&window, &[1, 3, 2, 4]);
+    }
+
+    #[test]
+    fn update_removes_rows() {
+        let mut window = window(vec![1, 2, 3]);
+        window.model_mut().items.remove(0);
+        assert!(window.update());
+        assert_rows(&window, &[2, 3]);
+        assert!(window.rects_with_key(1u64).is_empty());
+    }
+
+    #[test]
+    fn update_moves_rows() {
+        let mut window = window(vec![1, 2, 3]);
+        window.model_mut().items.move_item(0, 2);
+        window.model_mut().items.move_item(2, 1);
+        assert!(window.update());
+        assert_rows(&window, &[2, 1, 3]);
+    }
+
+    #[test]
+    fn update_rebuilds_changed_rows() {
+        let mut window = window(vec![1, 2, 3]);
+        window.model_mut().items.set(1, 5);
+        assert!(window.update());
+        assert_rows(&window, &[1, 5, 3]);
+        assert!(window.rects_with_key(2u64).is_empty());
+    }
+
+    #[test]
+    fn update_replays_changes_in_order() {
+        let mut window = window(vec![1, 2]);
+        let items = &window.model_mut().items;
+        items.push(3);
+        items.set(2, 4);
+        items.remove(0);
+        items.insert(0, 5);
+        assert!(window.update());
+        assert_rows(&window, &[5, 2, 4]);
+    }
+}
@@ -1,5 +1,9 @@
 mod box_layout;
 mod button;
+mod list;
+mod scroll_view;
 
 pub use box_layout::Box;
 pub use button::{Button, ButtonDelegate};
+pub use list::List;
+pub use scroll_view::ScrollView;
@@ -0,0 +1,169 @@
+use std::any::Any;
+
+use crate::description::BoxedDescription;
+use crate::events::{Event, EventResult};
+use crate::geom::{Constraints, Position, Rect, Scalar, Size, SizeHint};
+use crate::renderer::painter::Painter;
+use crate::view::{
+    KeyValue, LayoutContext, Widget, WidgetCache, WidgetKey, WidgetState, WidgetStateMut,
+    WidgetTree,
+};
+use crate::Description;
+
+/// How far one line of a scroll wheel scrolls, in logical pixels.
+const LINE_HEIGHT: Scalar = 20.0;
+
+/// The largest size the scroll view asks for, beyond this its content needs to be scrolled.
+const MAX_PREFERRED_SIZE: Size = Size {
+    width: 400.0,
+    height: 400.0,
+};
+
+const SCROLLBAR_WIDTH: Scalar = 6.0;
+
+const SCROLLBAR_COLOUR: [f32; 4] = [0.6, 0.6, 0.6, 1.0];
+
+/// Shows part of its child, which can be scrolled into view with the mouse wheel or touchpad.
+pub struct ScrollView<M> {
+    // Only None once taken by the cache
+    child: Option<BoxedDescription<M>>,
+    key: WidgetKey,
+}
+
+impl<M: 'static> ScrollView<M> {
+    #[track_caller]
+    pub fn new<D: Description<M> + 'static>(child: D) -> ScrollView<M> {
+        ScrollView {
+            child: Some(BoxedDescription::new(child)),
+            key: WidgetKey::caller(),
+        }
+    }
+
+    /// See `Button::with_key`.
+    pub fn with_key(mut self, value: impl Into<KeyValue>) -> Self {
+        self.key = self.key.with_value(value);
+        self
+    }
+}
+
+impl<M: 'static> Description<M> for ScrollView<M> {
+    fn key(&self) -> Option<WidgetKey> {
+        Some(self.key.clone())
+    }
+
+    fn children(&mut self) -> Vec<BoxedDescription<M>> {
+        self.child.take().into_iter().collect()
+    }
+
+    fn apply(self, obj: &mut dyn Any) -> Result<(), Self> {
+        // Nothing to update, the scroll offset is kept
+        match obj.downcast_mut::<ScrollViewWidget>() {
+            Some(_) => Ok(()),
+            None => Err(self),
+        }
+    }
+
+    fn create(self, cache: &mut WidgetCache<M>) -> WidgetTree {
+        cache.factory().new_widget(
+            self.key,
+            ScrollViewWidget {
+                offset: Position::zero(),
+                content_size: Size::zero(),
+            },
+        )
+    }
+}
+
+struct ScrollViewWidget {
+    offset: Position,
+    content_size: Size,
+}
+
+impl ScrollViewWidget {
+    /// Keeps the offset within the content, so that the content always fills the viewport.
+    fn clamp_offset(&self, offset: Position, viewport: Size) -> Position {
+        let max_x = (self.content_size.width - viewport.width).max(0.0);
+        let max_y = (self.content_size.height - viewport.height).max(0.0);
+        Position::new(offset.x.max(0.0).min(max_x), offset.y.max(0.0).min(max_y))
+    }
+}
+
+impl<M> Widget<M> for ScrollViewWidget {
+    fn event(&mut self, mut state: WidgetStateMut<'_, M>, event: Event) -> EventResult {
+        match event {
+            Event::Scroll(scroll_event) => {
+                let delta = scroll_event.delta.to_pixels(LINE_HEIGHT);
+                let offset = self.clamp_offset(self.offset - delta, state.rect().size);
+                if offset == self.offset {
+                    // Let an outer scroll view take over once this one reaches its end
+                    EventResult::Unhandled
+                } else {
+                    self.offset = offset;
+                    state.request_layout();
+                    EventResult::Handled
+                }
+            }
+            _ => EventResult::Unhandled,
+        }
+    }
+
+    fn paint(&self, _: WidgetState<'_, M>, _: &mut Painter) {}
+
+    fn paint_over_children(&self, state: WidgetState<'_, M>, painter: &mut Painter) {
+        let viewport = state.local_rect().size;
+        if self.content_size.height > viewport.height {
+            let height = viewport.height * viewport.height / self.content_size.height;
+            let y = self.offset.y / self.content_size.height * viewport.height;
+            painter.paint_quad(
+                Rect::new(
+                    Position::new(viewport.width - SCROLLBAR_WIDTH, y),
+                    Size::new(SCROLLBAR_WIDTH, height),
+                ),
+                SCROLLBAR_COLOUR,
+            );
+        }
+        if self.content_size.width > viewport.width {
+            let width = viewport.width * viewport.width / self.content_size.width;
+            let x = self.offset.x / self.content_size.width * viewport.width;
+            painter.paint_quad(
+                Rect::new(
+                    Position::new(x, viewport.height - SCROLLBAR_WIDTH),
+                    Size::new(width, SCROLLBAR_WIDTH),
+                ),
+                SCROLLBAR_COLOUR,
+            );
+        }
+    }
+
+    fn size_hint(&self, children: &[WidgetTree]) -> SizeHint {
+        let content = children[0].size_hint().preferred;
+        SizeHint::new(
+            Size::zero(),
+            Size::new(
+                content.width.min(MAX_PREFERRED_SIZE.width),
+                content.height.min(MAX_PREFERRED_SIZE.height),
+            ),
+            Size::infinite(),
+        )
+    }
+
+    fn layout(&mut self, children: &mut [WidgetTree], ctx: LayoutContext) -> Size {
+        let viewport = ctx
+            .constraints()
+            .constrain(Widget::<M>::size_hint(self, children).preferred);
+        // The content fills the viewport if it can, otherwise it takes its preferred size
+        let content_hint = children[0].size_hint();
+        let min_content = Size::new(
+            viewport.width.min(content_hint.max.width),
+            viewport.height.min(content_hint.max.height),
+        );
+        self.content_size =
+            Constraints::new(min_content, Size::infinite()).constrain(content_hint.preferred);
+        self.offset = self.clamp_offset(self.offset, viewport);
+        children[0].layout(LayoutContext::new(
+            ctx.origin() - self.offset,
+            Constraints::tight(self.content_size),
+        ));
+        viewport
+    }
+}
@@ -0,0 +1,234 @@
+//! `view!` and `#[derive(Description)]` used from outside of kakapo, as applications use them.
+
+use std::marker::PhantomData;
+
+use kakapo::events::{Event, EventResult};
+use kakapo::geom::{Position, Size, SizeHint};
+use kakapo::headless::HeadlessWindow;
+use kakapo::view::{
+    KeyValue, View, ViewRefs, Widget, WidgetCache, WidgetKey, WidgetState, WidgetStateMut,
+    WidgetTree,
+};
+use kakapo::view_model::{Observable, ViewModel};
+use kakapo::{view, widgets, Description, PaintCommand, Painter};
+
+#[derive(ViewModel)]
+struct Model {
+    view_refs: ViewRefs,
+    labels: Observable<Vec<u32>>,
+}
+
+impl Model {
+    fn new(labels: Vec<u32>) -> Model {
+        Model {
+            view_refs: ViewRefs::new(),
+            labels: Observable::new(labels),
+        }
+    }
+}
+
+/// Shows some text.
+#[derive(Description)]
+#[description(widget = "LabelWidget<M>", model_param = "M", bound = "M: 'static")]
+struct Label {
+    text: String,
+    key: WidgetKey,
+}
+
+impl Label {
+    #[track_caller]
+    fn new(text: impl Into<String>) -> Label {
+        Label {
+            text: text.into(),
+            key: WidgetKey::caller(),
+        }
+    }
+
+    fn with_key(mut self, value: impl Into<KeyValue>) -> Self {
+        self.key = self.key.with_value(value);
+        self
+    }
+}
+
+struct LabelWidget<M> {
+    text: String,
+    model: PhantomData<fn(&mut M)>,
+}
+
+impl<M> From<Label> for LabelWidget<M> {
+    fn from(label: Label) -> Self {
+        LabelWidget {
+            text: label.text,
+            model: PhantomData,
+        }
+    }
+}
+
+impl<M: 'static> Widget<M> for LabelWidget<M> {
+    fn event(&mut self, _: WidgetStateMut<'_, M>, _: Event) -> EventResult {
+        EventResult::Unhandled
+    }
+
+    fn paint(&self, state: WidgetState<'_, M>, painter: &mut Painter) {
+        painter.paint_text(
+            wgpu_glyph::Section::default()
+                .with_text(vec![wgpu_glyph::Text::new(&self.text)])
+                .with_screen_position(state.local_rect().origin),
+        );
+    }
+
+    fn size_hint(&self, _: &[WidgetTree]) -> SizeHint {
+        SizeHint::new(Size::zero(), Size::new(100.0, 20.0), Size::new(100.0, 20.0))
+    }
+}
+
+struct LabelsView {}
+
+impl View for LabelsView {
+    type Model = Model;
+
+    fn view(&mut self, cache: &mut WidgetCache<Model>, model: &Model) -> WidgetTree {
+        let labels = model.labels.get();
+        cache.build(view! {
+            widgets::Box {
+                Label("Labels")
+                for id in labels {
+                    Label(format!("Label {}", id), key = id)
+                }
+            }
+        })
+    }
+}
+
+fn painted_text(window: &mut HeadlessWindow<Model>) -> Vec<(String, Position)> {
+    let mut commands: Vec<PaintCommand> = Vec::new();
+    window.paint().replay(&mut commands);
+    commands
+        .into_iter()
+        .filter_map(|command| match command {
+            PaintCommand::Text { text, position, .. } => Some((text, position)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn views_build_derived_widgets() {
+    let mut window = HeadlessWindow::new(
+        LabelsView {},
+        Model::new(vec![1, 2]),
+        Size::new(200.0, 600.0),
+    );
+    assert_eq!(
+        painted_text(&mut window),
+        vec![
+            ("Labels".to_string(), Position::new(0.0, 0.0)),
+            ("Label 1".to_string(), Position::new(0.0, 20.0)),
+            ("Label 2".to_string(), Position::new(0.0, 40.0)),
+        ]
+    );
+
+    // The derived `apply` updates the kept widgets
+    window.model_mut().labels.set(vec![2, 3]);
+    assert!(window.update());
+    assert_eq!(window.rects_with_key(2u32).len(), 1);
+    assert!(window.rects_with_key(1u32).is_empty());
+    assert_eq!(
+        painted_text(&mut window),
+        vec![
+            ("Labels".to_string(), Position::new(0.0, 0.0)),
+            ("Label 2".to_string(), Position::new(0.0, 20.0)),
+            ("Label 3".to_string(), Position::new(0.0, 40.0)),
+        ]
+    );
+}
+
+/// Not `#[track_caller]`, so the key is where the label is within the view.
+fn label() -> Label {
+    view! { Label("label") }
+}
+
+#[test]
+fn element_keys_are_where_the_element_is_written() {
+    // Keys are the same wherever the view is built from
+    assert!(Description::<Model>::key(&label()) == Description::<Model>::key(&label()));
+
+    let keys = (0..2)
+        .map(|_| {
+            let mut labels = view! {
+                widgets::Box::<Model> {
+                    Label("first")
+                    Label("second")
+                }
+            }
+            .children();
+            (labels.remove(0).key(), labels.remove(0).key())
+        })
+        .collect::<Vec<_>>();
+    assert!(keys[0] == keys[1]);
+    // Rather than where `Label::new` is called within the macro's expansion
+    assert!(keys[0].0 != keys[0].1);
+
+    let first = view! { Label("label", key = 1u32) };
+    let second = view! { Label("label", key = 2u32) };
+    assert!(Description::<Model>::key(&first) != Description::<Model>::key(&second));
+    assert_eq!(
+        Description::<Model>::key(&first).unwrap().value(),
+        Some(&KeyValue::from(1u32))
+    );
+}
+
+/// Names which the expansions could pick up instead of what they mean.
+#[allow(dead_code)]
+mod hygiene {
+    use ::kakapo::view::WidgetKey;
+    use ::kakapo::{view, widgets, Description};
+
+    use super::Model;
+
+    mod kakapo {}
+    mod std {}
+    struct Option;
+    struct Result;
+    struct Some;
+    struct Ok;
+    trait Clone {}
+    trait From {}
+
+    #[derive(Description)]
+    #[description(widget = "super::LabelWidget<Model>", model = "Model")]
+    struct Label {
+        text: String,
+        key: WidgetKey,
+    }
+
+    impl ::std::convert::From<Label> for super::LabelWidget<Model> {
+        fn from(label: Label) -> Self {
+            super::Label::new(label.text).into()
+        }
+    }
+
+    impl Label {
+        #[track_caller]
+        fn new(text: &str) -> Label {
+            Label {
+                text: text.to_string(),
+                key: WidgetKey::caller(),
+            }
+        }
+    }
+
+    #[test]
+    fn expansions_only_use_their_own_names() {
+        let __element = "outer";
+        let mut labels = view! {
+            widgets::Box::<Model> {
+                Label(__element),
+                { Label::new(__element) }
+            }
+        }
+        .children();
+        assert_eq!(labels.len(), 2);
+        assert!(labels.remove(0).key() != labels.remove(0).key());
+    }
+}